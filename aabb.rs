@@ -1,9 +1,12 @@
 use nalgebra::vec::*;
-use Ts = nalgebra::traits::transformation::Transform;
 use scene;
+use scene::{minf, maxf};
+use fperror;
+use std::float;
 
 type Vec3f = Vec3<float>;
 
+#[deriving(Clone)]
 pub struct AABB {
     min: Vec3f,
     max: Vec3f
@@ -24,13 +27,25 @@ impl AABB {
         }
     }
 
-    pub fn transformed(&self, ts: &scene::Transform3d) -> AABB {
-        /* FIXME: need to find the new min and max points.. */
-        fail!("BUG!");
+    /// A box containing nothing; stretching it to another box yields that box.
+    pub fn empty() -> AABB {
         AABB {
-            min: ts.transform(&self.min),
-            max: ts.transform(&self.max)
+            min: Vec3::new(float::infinity, float::infinity, float::infinity),
+            max: Vec3::new(float::neg_infinity, float::neg_infinity, float::neg_infinity)
+        }
+    }
+
+    /// The smallest box enclosing this box after transformation by `ts`.
+    pub fn transformed(&self, ts: &scene::Transform3d) -> AABB {
+        let mut r = AABB::empty();
+        for &x in [self.min.x, self.max.x].iter() {
+            for &y in [self.min.y, self.max.y].iter() {
+                for &z in [self.min.z, self.max.z].iter() {
+                    r.stretch_to_point(&ts.transform(&Vec3::new(x, y, z)));
+                }
+            }
         }
+        r
     }
 
     pub fn stretch_to(&mut self, other: &AABB) {
@@ -41,5 +56,81 @@ impl AABB {
         if other.max.y > self.max.y { self.max.y = other.max.y }
         if other.max.z > self.max.z { self.max.z = other.max.z }
     }
+
+    pub fn stretch_to_point(&mut self, p: &Vec3f) {
+        if p.x < self.min.x { self.min.x = p.x }
+        if p.y < self.min.y { self.min.y = p.y }
+        if p.z < self.min.z { self.min.z = p.z }
+        if p.x > self.max.x { self.max.x = p.x }
+        if p.y > self.max.y { self.max.y = p.y }
+        if p.z > self.max.z { self.max.z = p.z }
+    }
+
+    pub fn centroid(&self) -> Vec3f {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn surface_area(&self) -> float {
+        let d = self.max - self.min;
+        if d.x < 0.0 || d.y < 0.0 || d.z < 0.0 { return 0.0 }
+        2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+    }
+
+    /// Slab test against a ray given by its origin and per-component inverse
+    /// direction. Returns the entry distance if the ray overlaps the box
+    /// somewhere in [t_min, t_max]. The exit distances are widened to cover
+    /// rounding error, so a ray grazing the box is never missed.
+    pub fn intersect(&self, pos: &Vec3f, dir_inv: &Vec3f, t_min: float, t_max: float) -> Option<float> {
+        let tx1 = (self.min.x - pos.x) * dir_inv.x;
+        let tx2 = (self.max.x - pos.x) * dir_inv.x;
+        let ty1 = (self.min.y - pos.y) * dir_inv.y;
+        let ty2 = (self.max.y - pos.y) * dir_inv.y;
+        let tz1 = (self.min.z - pos.z) * dir_inv.z;
+        let tz2 = (self.max.z - pos.z) * dir_inv.z;
+
+        let widen = 1.0 + 2.0 * fperror::gamma(3);
+        let tmin = maxf(maxf(minf(tx1, tx2), minf(ty1, ty2)), maxf(minf(tz1, tz2), t_min));
+        let tmax = minf(minf(maxf(tx1, tx2), maxf(ty1, ty2)) * widen, minf(maxf(tz1, tz2) * widen, t_max));
+
+        if tmin <= tmax { Some(tmin) } else { None }
+    }
 }
 
+#[cfg(test)]
+mod test {
+    use super::AABB;
+    use nalgebra::vec::*;
+    use transform::Affine;
+    use std::float;
+
+    fn assert_bounds(b: &AABB, min: Vec3<float>, max: Vec3<float>) {
+        let close = |a: &Vec3<float>, b: &Vec3<float>| {
+            let d = *a - *b;
+            d.x.abs() < 1e-9 && d.y.abs() < 1e-9 && d.z.abs() < 1e-9
+        };
+        assert!(close(&b.min, &min) && close(&b.max, &max),
+                fmt!("got %? to %?, expected %? to %?", b.min, b.max, min, max));
+    }
+
+    #[test]
+    fn translation() {
+        let b = AABB::from_min_max(Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 2.0, 3.0));
+        let ts = Affine::identity().translated(&Vec3::new(1.0, -2.0, 3.0));
+        assert_bounds(&b.transformed(&ts), Vec3::new(1.0, -2.0, 3.0), Vec3::new(2.0, 0.0, 6.0));
+    }
+
+    #[test]
+    fn rotation_by_45_degrees() {
+        let b = AABB::from_origin_extents(Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 1.0, 1.0));
+        let ts = Affine::rotation(&Vec3::new(0.0, 0.0, float::consts::pi / 4.0));
+        let r = float::consts::sqrt2;
+        assert_bounds(&b.transformed(&ts), Vec3::new(-r, -r, -1.0), Vec3::new(r, r, 1.0));
+    }
+
+    #[test]
+    fn non_uniform_scale() {
+        let b = AABB::from_min_max(Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 2.0, 3.0));
+        let ts = Affine::scaling(&Vec3::new(2.0, 0.5, -1.0));
+        assert_bounds(&b.transformed(&ts), Vec3::new(0.0, 0.0, -3.0), Vec3::new(2.0, 1.0, 0.0));
+    }
+}
@@ -0,0 +1,418 @@
+use nalgebra::vec::*;
+use std::float;
+use image::RGB;
+use random;
+use microfacet;
+
+type Vec3f = Vec3<float>;
+
+/// Surface parameters at the point being shaded. Directions passed to a
+/// BSDF are unit vectors in the local shading frame, where +z is the
+/// outward surface normal; `wo` points towards the viewer and `wi` towards
+/// the light.
+pub struct Shading {
+    albedo: RGB,
+    hemisphere: random::HemisphereSampling,
+    /// Roughness from a texture, replacing that of the microfacet lobes.
+    roughness: Option<float>
+}
+
+pub struct BSDFSample {
+    wi: Vec3f,
+    f: RGB,
+    pdf: float,
+    /// Chosen from a delta distribution; `f` and `pdf` are then only
+    /// meaningful as the ratio `f * |cos| / pdf`.
+    delta: bool
+}
+
+pub trait BSDF {
+    /// Chooses `wi` given `wo`, a point `u` of the unit square and `uc` in
+    /// [0, 1) for discrete choices such as reflection versus refraction.
+    fn sample(&self, sh: &Shading, wo: &Vec3f, u: (float, float), uc: float)
+        -> Option<BSDFSample>;
+    fn eval(&self, sh: &Shading, wo: &Vec3f, wi: &Vec3f) -> RGB;
+    /// Solid angle density of `sample` choosing `wi`.
+    fn pdf(&self, sh: &Shading, wo: &Vec3f, wi: &Vec3f) -> float;
+    /// True if `sample` only ever produces delta directions, so `eval` and
+    /// `pdf` are always zero.
+    fn is_delta(&self) -> bool;
+    fn clone_bsdf(&self) -> ~BSDF:Send+Freeze;
+}
+
+/// Orthonormal shading frame around a normal.
+pub struct Frame {
+    s: Vec3f,
+    t: Vec3f,
+    n: Vec3f
+}
+
+impl Frame {
+    pub fn from_normal(n: &Vec3f) -> Frame {
+        let (s, t) = random::coordinate_system(n);
+        Frame { s: s, t: t, n: *n }
+    }
+
+    /// A frame whose first axis follows `tangent` projected onto the plane
+    /// of `n`, so anisotropic lobes line up with the surface
+    /// parameterisation. Falls back to `from_normal` for a degenerate
+    /// tangent.
+    pub fn from_normal_tangent(n: &Vec3f, tangent: &Vec3f) -> Frame {
+        let s = *tangent - *n * n.dot(tangent);
+        let len = s.norm();
+        if !(len > 1e-9) { return Frame::from_normal(n) }
+        let s = s * (1.0 / len);
+        Frame { s: s, t: n.cross(&s), n: *n }
+    }
+
+    pub fn to_local(&self, v: &Vec3f) -> Vec3f {
+        Vec3::new(v.dot(&self.s), v.dot(&self.t), v.dot(&self.n))
+    }
+
+    pub fn to_world(&self, v: &Vec3f) -> Vec3f {
+        self.s * v.x + self.t * v.y + self.n * v.z
+    }
+}
+
+pub fn same_hemisphere(a: &Vec3f, b: &Vec3f) -> bool {
+    a.z * b.z > 0.0
+}
+
+pub fn reflect(wo: &Vec3f) -> Vec3f {
+    Vec3::new(-wo.x, -wo.y, wo.z)
+}
+
+/// Ideal diffuse reflection on both sides of the surface.
+pub struct Lambertian;
+
+impl BSDF for Lambertian {
+    fn sample(&self, sh: &Shading, wo: &Vec3f, u: (float, float), _uc: float)
+        -> Option<BSDFSample>
+    {
+        let (u1, u2) = u;
+        let (wi, pdf) = sh.hemisphere.sample(u1, u2);
+        let wi = if wo.z < 0.0 { Vec3::new(wi.x, wi.y, -wi.z) } else { wi };
+        if pdf == 0.0 { return None }
+        Some(BSDFSample { wi: wi, f: self.eval(sh, wo, &wi), pdf: pdf, delta: false })
+    }
+
+    fn eval(&self, sh: &Shading, wo: &Vec3f, wi: &Vec3f) -> RGB {
+        if !same_hemisphere(wo, wi) { return RGB::black() }
+        sh.albedo.mul_t(1.0 / float::consts::pi)
+    }
+
+    fn pdf(&self, sh: &Shading, wo: &Vec3f, wi: &Vec3f) -> float {
+        if !same_hemisphere(wo, wi) { return 0.0 }
+        sh.hemisphere.pdf(wi.z.abs())
+    }
+
+    fn is_delta(&self) -> bool { false }
+
+    fn clone_bsdf(&self) -> ~BSDF:Send+Freeze { ~Lambertian as ~BSDF:Send+Freeze }
+}
+
+/// Perfect mirror.
+pub struct SpecularReflection;
+
+impl BSDF for SpecularReflection {
+    fn sample(&self, sh: &Shading, wo: &Vec3f, _u: (float, float), _uc: float)
+        -> Option<BSDFSample>
+    {
+        let wi = reflect(wo);
+        if wi.z == 0.0 { return None }
+        Some(BSDFSample { wi: wi, f: sh.albedo.mul_t(1.0 / wi.z.abs()), pdf: 1.0, delta: true })
+    }
+
+    fn eval(&self, _sh: &Shading, _wo: &Vec3f, _wi: &Vec3f) -> RGB { RGB::black() }
+    fn pdf(&self, _sh: &Shading, _wo: &Vec3f, _wi: &Vec3f) -> float { 0.0 }
+    fn is_delta(&self) -> bool { true }
+
+    fn clone_bsdf(&self) -> ~BSDF:Send+Freeze { ~SpecularReflection as ~BSDF:Send+Freeze }
+}
+
+/// Unpolarised Fresnel reflectance at a dielectric interface, where `eta` is
+/// the ratio of the refractive indices on the incident and transmitted sides.
+pub fn fresnel_dielectric(cos_i: float, cos_t: float, eta: float) -> float {
+    let rs = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let rp = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    0.5 * (rs * rs + rp * rp)
+}
+
+/// Fresnel reflectance at the surface of a medium with index of refraction
+/// `ior`, for `cos_i` measured against the outward normal; a negative cosine
+/// means the light arrives from inside. Total internal reflection gives 1.
+pub fn fresnel_reflectance(cos_i: float, ior: float) -> float {
+    let (cos_i, eta) = if cos_i < 0.0 { (-cos_i, ior) } else { (cos_i, 1.0 / ior) };
+    let sin2_t = eta * eta * (1.0 - cos_i * cos_i);
+    if sin2_t >= 1.0 { return 1.0 }
+    fresnel_dielectric(cos_i, (1.0 - sin2_t).sqrt(), eta)
+}
+
+/// Refracts `w` through an interface with normal `n` on the same side as
+/// `w`, where `eta` is the ratio of the refractive indices on the side of
+/// `w` and the other side. None on total internal reflection.
+pub fn refract(w: &Vec3f, n: &Vec3f, eta: float) -> Option<Vec3f> {
+    let cos_i = w.dot(n);
+    let sin2_t = eta * eta * (1.0 - cos_i * cos_i);
+    if sin2_t >= 1.0 { return None }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    Some(-*w * eta + *n * (eta * cos_i - cos_t))
+}
+
+/// Smooth interface to a medium with index of refraction `ior`, on the
+/// side the normal points away from. Chooses between reflection and
+/// refraction by the Fresnel reflectance. Refracted radiance is scaled by
+/// the squared index ratio, as it is compressed into a smaller solid angle.
+pub struct SpecularDielectric {
+    ior: float
+}
+
+impl BSDF for SpecularDielectric {
+    fn sample(&self, sh: &Shading, wo: &Vec3f, _u: (float, float), uc: float)
+        -> Option<BSDFSample>
+    {
+        if wo.z == 0.0 { return None }
+        let cos_o = wo.z.abs();
+        let fr = fresnel_reflectance(wo.z, self.ior);
+
+        if uc < fr {
+            let wi = reflect(wo);
+            Some(BSDFSample { wi: wi, f: sh.albedo.mul_t(fr / cos_o), pdf: fr, delta: true })
+        } else {
+            // a negative cosine means the ray is leaving the object
+            let (n, eta) = if wo.z > 0.0 {
+                (Vec3::new(0.0, 0.0, 1.0), 1.0 / self.ior)
+            } else {
+                (Vec3::new(0.0, 0.0, -1.0), self.ior)
+            };
+            let wi = match refract(wo, &n, eta) {
+                Some(wi) => wi,
+                None => return None
+            };
+            let ft = (1.0 - fr) * eta * eta / wi.z.abs();
+            Some(BSDFSample { wi: wi, f: sh.albedo.mul_t(ft), pdf: 1.0 - fr, delta: true })
+        }
+    }
+
+    fn eval(&self, _sh: &Shading, _wo: &Vec3f, _wi: &Vec3f) -> RGB { RGB::black() }
+    fn pdf(&self, _sh: &Shading, _wo: &Vec3f, _wi: &Vec3f) -> float { 0.0 }
+    fn is_delta(&self) -> bool { true }
+
+    fn clone_bsdf(&self) -> ~BSDF:Send+Freeze {
+        ~SpecularDielectric { ior: self.ior } as ~BSDF:Send+Freeze
+    }
+}
+
+/// Unpolarised Fresnel reflectance of a conductor with complex index of
+/// refraction `eta + ik`, seen from a medium with index 1.
+pub fn fresnel_conductor(cos_i: float, eta: float, k: float) -> float {
+    let cos2 = cos_i * cos_i;
+    let sin2 = 1.0 - cos2;
+    let t0 = eta * eta - k * k - sin2;
+    let a2_plus_b2 = (t0 * t0 + 4.0 * eta * eta * k * k).sqrt();
+    let t1 = a2_plus_b2 + cos2;
+    let a = (0.5 * (a2_plus_b2 + t0)).sqrt();
+    let t2 = 2.0 * cos_i * a;
+    let rs = (t1 - t2) / (t1 + t2);
+    let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let rp = rs * (t3 - t4) / (t3 + t4);
+    0.5 * (rs + rp)
+}
+
+#[deriving(Clone)]
+pub enum ConductorFresnel {
+    /// Schlick's approximation from the reflectance at normal incidence.
+    SchlickF0(RGB),
+    /// Exact reflectance from per-channel `eta` and `k`.
+    ComplexIOR(RGB, RGB)
+}
+
+impl ConductorFresnel {
+    pub fn eval(&self, cos_i: float) -> RGB {
+        let cos_i = if cos_i < 0.0 { 0.0 } else if cos_i > 1.0 { 1.0 } else { cos_i };
+        match *self {
+            SchlickF0(ref f0) => {
+                let m = (1.0 - cos_i).pow(&5.0);
+                f0.add_v(&RGB::white().add_v(&f0.mul_t(-1.0)).mul_t(m))
+            }
+            ComplexIOR(ref eta, ref k) => RGB {
+                r: fresnel_conductor(cos_i, eta.r, k.r),
+                g: fresnel_conductor(cos_i, eta.g, k.g),
+                b: fresnel_conductor(cos_i, eta.b, k.b)
+            }
+        }
+    }
+}
+
+/// Mirrors `v` into the hemisphere of the normal.
+pub fn to_upper(v: &Vec3f) -> Vec3f {
+    if v.z < 0.0 { Vec3::new(v.x, v.y, -v.z) } else { *v }
+}
+
+/// Rough metal with a GGX microfacet distribution, reflecting on both sides
+/// of the surface. Nearly smooth surfaces fall back to a Fresnel-weighted
+/// mirror, where the microfacet terms are numerically unusable.
+pub struct RoughConductor {
+    distribution: microfacet::TrowbridgeReitz,
+    fresnel: ConductorFresnel
+}
+
+impl BSDF for RoughConductor {
+    fn sample(&self, sh: &Shading, wo: &Vec3f, u: (float, float), _uc: float)
+        -> Option<BSDFSample>
+    {
+        if wo.z == 0.0 { return None }
+        if self.distribution.effectively_smooth() {
+            let wi = reflect(wo);
+            let cos = wi.z.abs();
+            let f = sh.albedo.mul_v(&self.fresnel.eval(cos)).mul_t(1.0 / cos);
+            return Some(BSDFSample { wi: wi, f: f, pdf: 1.0, delta: true });
+        }
+
+        let wo_up = to_upper(wo);
+        let wm = self.distribution.at(sh.roughness).sample_visible(&wo_up, u);
+        let wi = microfacet::reflect(&wo_up, &wm);
+        if wi.z <= 0.0 { return None }
+        let wi = if wo.z < 0.0 { Vec3::new(wi.x, wi.y, -wi.z) } else { wi };
+
+        let pdf = self.pdf(sh, wo, &wi);
+        if pdf == 0.0 { return None }
+        Some(BSDFSample { wi: wi, f: self.eval(sh, wo, &wi), pdf: pdf, delta: false })
+    }
+
+    fn eval(&self, sh: &Shading, wo: &Vec3f, wi: &Vec3f) -> RGB {
+        if !same_hemisphere(wo, wi) || self.distribution.effectively_smooth() {
+            return RGB::black()
+        }
+        let distribution = self.distribution.at(sh.roughness);
+        let (wo, wi) = (to_upper(wo), to_upper(wi));
+        let wm = (wo + wi).normalized();
+        let d = distribution.d(&wm);
+        let g = distribution.g(&wo, &wi);
+        sh.albedo.mul_v(&self.fresnel.eval(wo.dot(&wm)))
+                 .mul_t(d * g / (4.0 * wo.z * wi.z))
+    }
+
+    fn pdf(&self, sh: &Shading, wo: &Vec3f, wi: &Vec3f) -> float {
+        if !same_hemisphere(wo, wi) || self.distribution.effectively_smooth() {
+            return 0.0
+        }
+        let (wo, wi) = (to_upper(wo), to_upper(wi));
+        let wm = (wo + wi).normalized();
+        self.distribution.at(sh.roughness).d_visible(&wo, &wm) / (4.0 * wo.dot(&wm).abs())
+    }
+
+    fn is_delta(&self) -> bool { self.distribution.effectively_smooth() }
+
+    fn clone_bsdf(&self) -> ~BSDF:Send+Freeze {
+        ~RoughConductor { distribution: self.distribution.clone(), fresnel: self.fresnel.clone() }
+            as ~BSDF:Send+Freeze
+    }
+}
+
+/// Rough interface to a medium with index of refraction `ior`, after Walter
+/// et al., "Microfacet Models for Refraction through Rough Surfaces".
+/// Microfacet normals are sampled from the GGX visible normals and reflection
+/// or refraction is chosen by their Fresnel reflectance. Nearly smooth
+/// surfaces are handled as a `SpecularDielectric`.
+pub struct RoughDielectric {
+    ior: float,
+    distribution: microfacet::TrowbridgeReitz
+}
+
+impl RoughDielectric {
+    /// The microfacet normal halfway between `wo` and `wi` for reflection,
+    /// or its generalisation for refraction, facing +z. Also returns the
+    /// ratio of the refractive indices on the sides of `wi` and `wo`. None
+    /// if the pair is not connected by a microfacet visible from both.
+    fn half_vector(&self, wo: &Vec3f, wi: &Vec3f) -> Option<(Vec3f, float)> {
+        if wo.z == 0.0 || wi.z == 0.0 { return None }
+        let etap = if same_hemisphere(wo, wi) { 1.0 }
+                   else if wo.z > 0.0 { self.ior }
+                   else { 1.0 / self.ior };
+        let wm = *wi * etap + *wo;
+        let len = wm.norm();
+        if len == 0.0 { return None }
+        let wm = wm * (1.0 / len);
+        let wm = if wm.z < 0.0 { -wm } else { wm };
+        if wm.dot(wi) * wi.z < 0.0 || wm.dot(wo) * wo.z < 0.0 { return None }
+        Some((wm, etap))
+    }
+}
+
+impl BSDF for RoughDielectric {
+    fn sample(&self, sh: &Shading, wo: &Vec3f, u: (float, float), uc: float)
+        -> Option<BSDFSample>
+    {
+        if self.distribution.effectively_smooth() {
+            return SpecularDielectric { ior: self.ior }.sample(sh, wo, u, uc);
+        }
+        if wo.z == 0.0 { return None }
+
+        let wo_up = if wo.z < 0.0 { -*wo } else { *wo };
+        let wm = self.distribution.at(sh.roughness).sample_visible(&wo_up, u);
+        let fr = fresnel_reflectance(wo.dot(&wm), self.ior);
+
+        let wi = if uc < fr {
+            let wi = microfacet::reflect(wo, &wm);
+            if !same_hemisphere(wo, &wi) { return None }
+            wi
+        } else {
+            let (n, eta) = if wo.z > 0.0 { (wm, 1.0 / self.ior) } else { (-wm, self.ior) };
+            match refract(wo, &n, eta) {
+                Some(wi) if !same_hemisphere(wo, &wi) && wi.z != 0.0 => wi,
+                _ => return None
+            }
+        };
+
+        let pdf = self.pdf(sh, wo, &wi);
+        if pdf == 0.0 { return None }
+        Some(BSDFSample { wi: wi, f: self.eval(sh, wo, &wi), pdf: pdf, delta: false })
+    }
+
+    fn eval(&self, sh: &Shading, wo: &Vec3f, wi: &Vec3f) -> RGB {
+        if self.distribution.effectively_smooth() { return RGB::black() }
+        let (wm, etap) = match self.half_vector(wo, wi) {
+            Some(h) => h,
+            None => return RGB::black()
+        };
+        let distribution = self.distribution.at(sh.roughness);
+        let fr = fresnel_reflectance(wo.dot(&wm), self.ior);
+        let d = distribution.d(&wm);
+        let g = distribution.g(wo, wi);
+
+        if same_hemisphere(wo, wi) {
+            sh.albedo.mul_t(d * g * fr / (4.0 * wo.z * wi.z).abs())
+        } else {
+            let denom = wi.dot(&wm) + wo.dot(&wm) / etap;
+            let ft = d * (1.0 - fr) * g *
+                     (wi.dot(&wm) * wo.dot(&wm) / (wi.z * wo.z * denom * denom)).abs();
+            sh.albedo.mul_t(ft / (etap * etap))
+        }
+    }
+
+    fn pdf(&self, sh: &Shading, wo: &Vec3f, wi: &Vec3f) -> float {
+        if self.distribution.effectively_smooth() { return 0.0 }
+        let (wm, etap) = match self.half_vector(wo, wi) {
+            Some(h) => h,
+            None => return 0.0
+        };
+        let fr = fresnel_reflectance(wo.dot(&wm), self.ior);
+        let pdf_wm = self.distribution.at(sh.roughness).d_visible(wo, &wm);
+
+        if same_hemisphere(wo, wi) {
+            pdf_wm / (4.0 * wo.dot(&wm).abs()) * fr
+        } else {
+            let denom = wi.dot(&wm) + wo.dot(&wm) / etap;
+            pdf_wm * wi.dot(&wm).abs() / (denom * denom) * (1.0 - fr)
+        }
+    }
+
+    fn is_delta(&self) -> bool { self.distribution.effectively_smooth() }
+
+    fn clone_bsdf(&self) -> ~BSDF:Send+Freeze {
+        ~RoughDielectric { ior: self.ior, distribution: self.distribution.clone() }
+            as ~BSDF:Send+Freeze
+    }
+}
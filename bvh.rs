@@ -0,0 +1,254 @@
+use nalgebra::vec::*;
+use std::{vec, float, iterator};
+use aabb::AABB;
+use scene;
+
+type Vec3f = Vec3<float>;
+
+static NUM_BINS: uint = 16;
+static MAX_LEAF_PRIMS: uint = 8;
+static TRAVERSAL_COST: float = 1.0;
+static INTERSECTION_COST: float = 1.0;
+
+/// A node of the flattened tree. Interior nodes have `count == 0`; their
+/// first child directly follows them and `offset` is the index of the second
+/// child. Leaves reference `count` primitives starting at `offset` in
+/// `Tree::order`.
+pub struct Node {
+    bounds: AABB,
+    offset: uint,
+    count: uint,
+    axis: uint
+}
+
+/// A bounding volume hierarchy over an indexed set of primitives, built with
+/// the binned surface area heuristic.
+pub struct Tree {
+    nodes: ~[Node],
+    order: ~[uint]
+}
+
+pub struct Stats {
+    nodes: uint,
+    leaves: uint,
+    depth: uint,
+    max_leaf_prims: uint
+}
+
+struct BuildPrim {
+    bounds: AABB,
+    centroid: Vec3f
+}
+
+#[deriving(Clone)]
+struct Bin {
+    bounds: AABB,
+    count: uint
+}
+
+fn axis_of(v: &Vec3f, axis: uint) -> float {
+    match axis {
+        0 => v.x,
+        1 => v.y,
+        _ => v.z
+    }
+}
+
+impl Tree {
+    pub fn build(boxes: &[AABB]) -> Tree {
+        let prims: ~[BuildPrim] = boxes.iter().map(|b| {
+            BuildPrim { bounds: b.clone(), centroid: b.centroid() }
+        }).collect();
+        let mut tree = Tree {
+            nodes: vec::with_capacity(2 * boxes.len()),
+            order: vec::from_fn(boxes.len(), |i| i)
+        };
+        if boxes.len() > 0 {
+            tree.build_node(prims, 0, boxes.len());
+        }
+        tree
+    }
+
+    fn build_node(&mut self, prims: &[BuildPrim], start: uint, end: uint) -> uint {
+        let mut bounds = AABB::empty();
+        let mut centroids = AABB::empty();
+        for &i in self.order.slice(start, end).iter() {
+            bounds.stretch_to(&prims[i].bounds);
+            centroids.stretch_to_point(&prims[i].centroid);
+        }
+
+        let index = self.nodes.len();
+        self.nodes.push(Node { bounds: bounds, offset: start, count: end - start, axis: 0 });
+
+        let n = end - start;
+        if n == 1 {
+            return index;
+        }
+
+        let extent = centroids.max - centroids.min;
+        let axis = if extent.x > extent.y && extent.x > extent.z { 0 }
+                   else if extent.y > extent.z { 1 }
+                   else { 2 };
+        let cmin = axis_of(&centroids.min, axis);
+        let cext = axis_of(&extent, axis);
+        if cext <= 0.0 {
+            // all centroids coincide, no split can separate them
+            return index;
+        }
+
+        let bin_of = |p: &BuildPrim| -> uint {
+            let b = ((axis_of(&p.centroid, axis) - cmin) / cext * (NUM_BINS as float)) as uint;
+            if b >= NUM_BINS { NUM_BINS - 1 } else { b }
+        };
+
+        let mut bins = vec::from_elem(NUM_BINS, Bin { bounds: AABB::empty(), count: 0 });
+        for &i in self.order.slice(start, end).iter() {
+            let b = bin_of(&prims[i]);
+            bins[b].count += 1;
+            bins[b].bounds.stretch_to(&prims[i].bounds);
+        }
+
+        // cost of splitting after bin i, i.e. bins [0, i] to the left
+        let mut right_area = vec::from_elem(NUM_BINS, 0.0);
+        let mut right_count = vec::from_elem(NUM_BINS, 0u);
+        let mut acc = AABB::empty();
+        let mut count = 0u;
+        let mut i = NUM_BINS - 1;
+        while i > 0 {
+            acc.stretch_to(&bins[i].bounds);
+            count += bins[i].count;
+            right_area[i] = acc.surface_area();
+            right_count[i] = count;
+            i -= 1;
+        }
+
+        let mut best_cost = float::infinity;
+        let mut best_split = 0u;
+        let mut acc = AABB::empty();
+        let mut count = 0u;
+        for i in iterator::range(0, NUM_BINS - 1) {
+            acc.stretch_to(&bins[i].bounds);
+            count += bins[i].count;
+            if count == 0 || right_count[i+1] == 0 { loop }
+            let cost = acc.surface_area() * (count as float) +
+                       right_area[i+1] * (right_count[i+1] as float);
+            if cost < best_cost {
+                best_cost = cost;
+                best_split = i;
+            }
+        }
+
+        let leaf_cost = INTERSECTION_COST * (n as float);
+        let split_cost = TRAVERSAL_COST +
+                         INTERSECTION_COST * best_cost / bounds.surface_area();
+        if best_cost == float::infinity || (n <= MAX_LEAF_PRIMS && split_cost >= leaf_cost) {
+            return index;
+        }
+
+        let mut mid = start;
+        for i in iterator::range(start, end) {
+            if bin_of(&prims[self.order[i]]) <= best_split {
+                self.order.swap(i, mid);
+                mid += 1;
+            }
+        }
+
+        self.build_node(prims, start, mid);
+        let second = self.build_node(prims, mid, end);
+        self.nodes[index].offset = second;
+        self.nodes[index].count = 0;
+        self.nodes[index].axis = axis;
+        index
+    }
+
+    /// Walks the nodes whose bounds the ray enters within its interval,
+    /// calling `hit` with each candidate primitive and the current closest
+    /// distance. `hit` returns the distance of an intersection closer than
+    /// that, which then becomes the new bound.
+    pub fn traverse(&self, ray: &scene::Ray, hit: &fn(prim: uint, t_max: float) -> Option<float>) {
+        if self.nodes.len() == 0 { return }
+
+        let dir_inv = Vec3::new(1.0 / ray.dir.x, 1.0 / ray.dir.y, 1.0 / ray.dir.z);
+        let dir_neg = [dir_inv.x < 0.0, dir_inv.y < 0.0, dir_inv.z < 0.0];
+        let mut t_max = ray.t_max;
+        let mut stack = ~[0u];
+
+        while stack.len() > 0 {
+            let index = stack.pop();
+            let node = &self.nodes[index];
+            if node.bounds.intersect(&ray.pos, &dir_inv, ray.t_min, t_max).is_none() { loop }
+
+            if node.count > 0 {
+                for &prim in self.order.slice(node.offset, node.offset + node.count).iter() {
+                    match hit(prim, t_max) {
+                        Some(t) if t < t_max => t_max = t,
+                        _ => ()
+                    }
+                }
+            } else if dir_neg[node.axis] {
+                stack.push(index + 1);
+                stack.push(node.offset);
+            } else {
+                stack.push(node.offset);
+                stack.push(index + 1);
+            }
+        }
+    }
+
+    pub fn stats(&self) -> Stats {
+        let mut stats = Stats { nodes: self.nodes.len(), leaves: 0, depth: 0, max_leaf_prims: 0 };
+        if self.nodes.len() == 0 { return stats }
+
+        let mut stack = ~[(0u, 1u)];
+        while stack.len() > 0 {
+            let (index, depth) = stack.pop();
+            let node = &self.nodes[index];
+            if depth > stats.depth { stats.depth = depth }
+            if node.count > 0 {
+                stats.leaves += 1;
+                if node.count > stats.max_leaf_prims { stats.max_leaf_prims = node.count }
+            } else {
+                stack.push((index + 1, depth + 1));
+                stack.push((node.offset, depth + 1));
+            }
+        }
+        stats
+    }
+}
+
+/// Drop-in replacement for `scene::LinearScene` that only tests the objects
+/// whose bounding boxes the ray passes through.
+pub struct BVHScene {
+    objs: ~[scene::Object],
+    tree: Tree
+}
+
+impl BVHScene {
+    pub fn new(objs: ~[scene::Object]) -> BVHScene {
+        let boxes: ~[AABB] = objs.iter().map(|o| o.bounding_box()).collect();
+        BVHScene {
+            tree: Tree::build(boxes),
+            objs: objs
+        }
+    }
+}
+
+impl scene::Scene for BVHScene {
+    fn intersect<'a>(&'a self, ray: &scene::Ray) -> Option<scene::Intersection<'a>> {
+        let mut closest = None;
+        do self.tree.traverse(ray) |i, t_max| {
+            match self.objs[i].hit(&ray.clipped(t_max)) {
+                Some(hit) => {
+                    closest = Some((i, hit));
+                    Some(hit.distance)
+                }
+                _ => None
+            }
+        }
+        closest.map(|&(i, hit)| self.objs[i].intersection(ray, &hit))
+    }
+
+    fn objects<'a>(&'a self) -> &'a [scene::Object] {
+        self.objs.as_slice()
+    }
+}
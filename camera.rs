@@ -49,12 +49,10 @@ impl Camera {
 
     pub fn make_ray(&self, x: float, y: float) -> scene::Ray {
         let c = self.cache.get_ref();
-        scene::Ray {
-            pos: self.position,
-            dir: (c.localat +
-                  c.hori * (2.0 * x - 1.0) +
-                  c.vert * (2.0 * y - 1.0)
-                 ).normalized()
-        }
+        scene::Ray::new(self.position,
+                        (c.localat +
+                         c.hori * (2.0 * x - 1.0) +
+                         c.vert * (2.0 * y - 1.0)
+                        ).normalized())
     }
 }
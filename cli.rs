@@ -0,0 +1,168 @@
+use std::{uint, float, u64};
+use tonemap;
+use sampler;
+use random;
+use extra::getopts::groups;
+use extra::getopts::{opt_present, opt_maybe_str, fail_str};
+
+pub enum Accel {
+    LinearAccel,
+    BVHAccel
+}
+
+pub struct Args {
+    scene: Option<~str>,
+    output: ~str,
+    bit_depth: uint,
+    exposure: float,
+    tonemap: tonemap::Operator,
+    width: Option<uint>,
+    height: Option<uint>,
+    samples: Option<uint>,
+    workers: Option<uint>,
+    time_limit: Option<float>,
+    seed: Option<u64>,
+    headless: bool,
+    accel: Accel,
+    sampler: sampler::SamplerKind,
+    hemisphere: random::HemisphereSampling
+}
+
+fn options() -> ~[groups::OptGroup] {
+    ~[
+        groups::optopt("o", "output", "output image path, .ppm, .png, .pfm or .hdr (default output.ppm)", "FILE"),
+        groups::optopt("", "bit-depth", "bits per channel for PNG output: 8 or 16 (default 8)", "BITS"),
+        groups::optopt("e", "exposure", "exposure adjustment in stops (default 0)", "STOPS"),
+        groups::optopt("", "tonemap", "tone mapping: clamp, reinhard, filmic or hable (default clamp)", "OP"),
+        groups::optopt("W", "width", "image width in pixels", "PIXELS"),
+        groups::optopt("H", "height", "image height in pixels", "PIXELS"),
+        groups::optopt("s", "samples", "stop after this many samples per pixel", "N"),
+        groups::optopt("j", "workers", "number of render tasks (default 8)", "N"),
+        groups::optopt("t", "time-limit", "stop after this many seconds", "SECONDS"),
+        groups::optopt("", "sampler", "sample generator: independent, stratified, halton or sobol (default independent)", "KIND"),
+        groups::optopt("", "hemisphere", "diffuse bounce sampling: cosine or uniform (default cosine)", "KIND"),
+        groups::optopt("", "seed", "random seed; equal seeds, resolutions and sample counts give identical images (default 0)", "N"),
+        groups::optopt("a", "accel", "intersection acceleration: linear or bvh (default linear)", "KIND"),
+        groups::optflag("", "headless", "render without a preview window until --samples or --time-limit is reached"),
+        groups::optflag("h", "help", "print this help and exit")
+    ]
+}
+
+pub fn usage(program: &str) -> ~str {
+    groups::usage(fmt!("Usage: %s [options] [SCENE.json]", program), options())
+}
+
+fn parse_uint(s: &str, name: &str) -> Result<uint, ~str> {
+    match uint::from_str(s) {
+        Some(n) if n > 0 => Ok(n),
+        _ => Err(fmt!("--%s: expected a positive integer, got '%s'", name, s))
+    }
+}
+
+macro_rules! opt_with(
+    ($m:expr, $name:expr, $parse:ident) => (
+        match opt_maybe_str($m, $name) {
+            Some(s) => match $parse(s, $name) {
+                Ok(v) => Some(v),
+                Err(e) => return Err(e)
+            },
+            None => None
+        }
+    )
+)
+
+fn parse_seconds(s: &str, name: &str) -> Result<float, ~str> {
+    match float::from_str(s) {
+        Some(t) if t > 0.0 => Ok(t),
+        _ => Err(fmt!("--%s: expected a positive number of seconds, got '%s'", name, s))
+    }
+}
+
+fn parse_seed(s: &str, name: &str) -> Result<u64, ~str> {
+    match u64::from_str(s) {
+        Some(n) => Ok(n),
+        None => Err(fmt!("--%s: expected an unsigned integer, got '%s'", name, s))
+    }
+}
+
+fn parse_bit_depth(s: &str, name: &str) -> Result<uint, ~str> {
+    match s {
+        "8" => Ok(8),
+        "16" => Ok(16),
+        _ => Err(fmt!("--%s: expected 8 or 16, got '%s'", name, s))
+    }
+}
+
+fn parse_exposure(s: &str, name: &str) -> Result<float, ~str> {
+    match float::from_str(s) {
+        Some(e) => Ok(e),
+        None => Err(fmt!("--%s: expected a number of stops, got '%s'", name, s))
+    }
+}
+
+fn parse_tonemap(s: &str, name: &str) -> Result<tonemap::Operator, ~str> {
+    match tonemap::Operator::from_str(s) {
+        Some(op) => Ok(op),
+        None => Err(fmt!("--%s: expected clamp, reinhard, filmic or hable, got '%s'", name, s))
+    }
+}
+
+fn parse_sampler(s: &str, name: &str) -> Result<sampler::SamplerKind, ~str> {
+    match sampler::SamplerKind::from_str(s) {
+        Some(k) => Ok(k),
+        None => Err(fmt!("--%s: expected independent, stratified, halton or sobol, got '%s'", name, s))
+    }
+}
+
+fn parse_hemisphere(s: &str, name: &str) -> Result<random::HemisphereSampling, ~str> {
+    match random::HemisphereSampling::from_str(s) {
+        Some(h) => Ok(h),
+        None => Err(fmt!("--%s: expected cosine or uniform, got '%s'", name, s))
+    }
+}
+
+fn parse_accel(s: &str, name: &str) -> Result<Accel, ~str> {
+    match s {
+        "linear" => Ok(LinearAccel),
+        "bvh" => Ok(BVHAccel),
+        _ => Err(fmt!("--%s: expected 'linear' or 'bvh', got '%s'", name, s))
+    }
+}
+
+/// Parses the command line (without the program name). Returns `Ok(None)`
+/// when --help was requested.
+pub fn parse(args: &[~str]) -> Result<Option<Args>, ~str> {
+    let m = match groups::getopts(args, options()) {
+        Ok(m) => m,
+        Err(f) => return Err(fail_str(f))
+    };
+
+    if opt_present(&m, "help") {
+        return Ok(None);
+    }
+
+    if m.free.len() > 1 {
+        return Err(fmt!("expected at most one scene file, got %u", m.free.len()));
+    }
+
+    Ok(Some(Args {
+        scene: if m.free.len() == 1 { Some(m.free[0].clone()) } else { None },
+        output: match opt_maybe_str(&m, "output") {
+            Some(o) => o,
+            None => ~"output.ppm"
+        },
+        bit_depth: opt_with!(&m, "bit-depth", parse_bit_depth).unwrap_or(8),
+        exposure: opt_with!(&m, "exposure", parse_exposure).unwrap_or(0.0),
+        tonemap: opt_with!(&m, "tonemap", parse_tonemap).unwrap_or(tonemap::Clamp),
+        width: opt_with!(&m, "width", parse_uint),
+        height: opt_with!(&m, "height", parse_uint),
+        samples: opt_with!(&m, "samples", parse_uint),
+        workers: opt_with!(&m, "workers", parse_uint),
+        time_limit: opt_with!(&m, "time-limit", parse_seconds),
+        seed: opt_with!(&m, "seed", parse_seed),
+        headless: opt_present(&m, "headless"),
+        accel: opt_with!(&m, "accel", parse_accel).unwrap_or(LinearAccel),
+        sampler: opt_with!(&m, "sampler", parse_sampler).unwrap_or(sampler::IndependentSampler),
+        hemisphere: opt_with!(&m, "hemisphere", parse_hemisphere).unwrap_or(random::CosineWeighted)
+    }))
+}
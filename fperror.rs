@@ -0,0 +1,50 @@
+use std::{cast, f64};
+use nalgebra::vec::*;
+
+type Vec3f = Vec3<float>;
+
+/// Half the distance between 1 and the next double.
+static MACHINE_EPSILON: float = 1.1102230246251565e-16;
+
+/// Bound on the relative error of `n` successive rounded operations, as in
+/// Pharr, Jakob and Humphreys, "Physically Based Rendering", 3rd ed.,
+/// section 3.9.
+pub fn gamma(n: uint) -> float {
+    let ne = (n as float) * MACHINE_EPSILON;
+    ne / (1.0 - ne)
+}
+
+/// The smallest double greater than `v`.
+pub fn next_float_up(v: float) -> float {
+    let v = v as f64;
+    if v == f64::infinity { return v as float }
+    // skip from -0 straight to the smallest positive double
+    let v = if v == -0.0 { 0.0 } else { v };
+    let bits: u64 = unsafe { cast::transmute(v) };
+    let bits = if v >= 0.0 { bits + 1 } else { bits - 1 };
+    let r: f64 = unsafe { cast::transmute(bits) };
+    r as float
+}
+
+/// The largest double less than `v`.
+pub fn next_float_down(v: float) -> float {
+    -next_float_up(-v)
+}
+
+pub fn abs_vec(v: &Vec3f) -> Vec3f {
+    Vec3::new(v.x.abs(), v.y.abs(), v.z.abs())
+}
+
+/// Offsets `p`, which lies within `p_error` of a surface with normal `n`,
+/// far enough along the normal that a ray leaving it in direction `w` can't
+/// hit the same surface again through rounding error.
+pub fn offset_ray_origin(p: &Vec3f, p_error: &Vec3f, n: &Vec3f, w: &Vec3f) -> Vec3f {
+    let d = abs_vec(n).dot(p_error);
+    let offset = if w.dot(n) < 0.0 { *n * -d } else { *n * d };
+    let po = *p + offset;
+    // round away from p so the offset is not lost when it is added
+    let round = |po: float, off: float| {
+        if off > 0.0 { next_float_up(po) } else if off < 0.0 { next_float_down(po) } else { po }
+    };
+    Vec3::new(round(po.x, offset.x), round(po.y, offset.y), round(po.z, offset.z))
+}
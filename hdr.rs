@@ -0,0 +1,59 @@
+use std::{cast, iterator};
+use image::RGB;
+
+fn push_f32_le(out: &mut ~[u8], v: float) {
+    let bits: u32 = unsafe { cast::transmute(v as f32) };
+    out.push(bits as u8);
+    out.push((bits >> 8) as u8);
+    out.push((bits >> 16) as u8);
+    out.push((bits >> 24) as u8);
+}
+
+/// Portable float map: little-endian 32-bit floats, rows stored bottom to
+/// top. `data` is row-major with the top row first.
+pub fn encode_pfm(width: uint, height: uint, data: &[RGB]) -> ~[u8] {
+    assert!(data.len() == width * height);
+    let mut out = fmt!("PF\n%u %u\n-1.0\n", width, height).as_bytes().to_owned();
+    for row in iterator::range(0, height) {
+        let y = height - 1 - row;
+        for c in data.slice(y * width, (y + 1) * width).iter() {
+            push_f32_le(&mut out, c.r);
+            push_f32_le(&mut out, c.g);
+            push_f32_le(&mut out, c.b);
+        }
+    }
+    out
+}
+
+/// Shared-exponent encoding of a colour as used by Radiance.
+fn rgbe(c: &RGB) -> [u8, ..4] {
+    let r = if c.r > 0.0 { c.r } else { 0.0 };
+    let g = if c.g > 0.0 { c.g } else { 0.0 };
+    let b = if c.b > 0.0 { c.b } else { 0.0 };
+    let v = if r > g { if r > b { r } else { b } } else { if g > b { g } else { b } };
+    if v < 1e-32 {
+        return [0, 0, 0, 0];
+    }
+
+    // v = m * 2^e with m in [0.5, 1)
+    let mut e = v.log2().floor() as int + 1;
+    if v / (2.0f).pow(&(e as float)) >= 1.0 { e += 1 }
+    let scale = 256.0 / (2.0f).pow(&(e as float));
+    let q = |x: float| -> u8 {
+        let s = x * scale;
+        if s >= 255.0 { 255 } else { s as u8 }
+    };
+    [q(r), q(g), q(b), (e + 128) as u8]
+}
+
+/// Radiance RGBE picture with flat (not run-length encoded) scanlines, top
+/// row first.
+pub fn encode_rgbe(width: uint, height: uint, data: &[RGB]) -> ~[u8] {
+    assert!(data.len() == width * height);
+    let mut out = fmt!("#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y %u +X %u\n",
+                       height, width).as_bytes().to_owned();
+    for c in data.iter() {
+        out.push_all(rgbe(c));
+    }
+    out
+}
@@ -1,4 +1,7 @@
 use std::{io, iterator};
+use png;
+use hdr;
+use tonemap::DisplayTransform;
 
 #[deriving(Clone, Eq, Encodable)]
 pub struct RGB { r: float, g: float, b: float }
@@ -7,6 +10,10 @@ impl RGB {
     pub fn add_v(&self, c: &RGB) -> RGB { RGB { r: self.r + c.r, g: self.g + c.g, b: self.b + c.b } }
     pub fn mul_v(&self, c: &RGB) -> RGB { RGB { r: self.r * c.r, g: self.g * c.g, b: self.b * c.b } }
     pub fn mul_t(&self, c: float) -> RGB { RGB { r: self.r * c, g: self.g * c, b: self.b * c } }
+    pub fn is_black(&self) -> bool { self.r == 0.0 && self.g == 0.0 && self.b == 0.0 }
+    pub fn lerp(&self, c: &RGB, t: float) -> RGB { self.mul_t(1.0 - t).add_v(&c.mul_t(t)) }
+    /// Relative luminance of linear Rec. 709 primaries.
+    pub fn luminance(&self) -> float { 0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b }
 
     pub fn black() -> RGB { RGB { r: 0.0, g: 0.0, b: 0.0 }}
     pub fn white() -> RGB { RGB { r: 1.0, g: 1.0, b: 1.0 }}
@@ -14,6 +21,11 @@ impl RGB {
     pub fn blue() -> RGB { RGB { r: 0.0, g: 0.0, b: 1.0 }}
 }
 
+/// Scalar counterpart of `RGB::lerp`: `a` at `t` = 0, `b` at `t` = 1.
+pub fn lerp(a: float, b: float, t: float) -> float {
+    a * (1.0 - t) + b * t
+}
+
 pub struct Image {
     data: ~[RGB],
     iters: uint,
@@ -21,11 +33,6 @@ pub struct Image {
     h: uint
 }
 
-fn clamp(x: float) -> float {
-    if x > 1.0 { 1.0 }
-    else { x }
-}
-
 impl Image {
     pub fn new(w: uint, h: uint) -> Image {
         let mut i = Image { data: ~[], iters: 0, w: w, h: h };
@@ -33,22 +40,45 @@ impl Image {
         i
     }
 
-    pub fn to_ppm(&self) -> ~str {
+    pub fn to_ppm(&self, dt: &DisplayTransform) -> ~str {
         do io::with_str_writer |wr| {
             wr.write_line(fmt!("P3 %? %? 255", self.w, self.h));
             for y in iterator::range(0, self.h) {
                 for x in iterator::range(0, self.w) {
-                    let color = self.data[y*self.w+x];
-                    let cs = [clamp(color.r), clamp(color.g), clamp(color.b)];
-                    for &v in cs.iter() {
-                        let vs = [(v*255.0).to_int(), 255];
-                        wr.write_str(fmt!("%? ", vs.iter().min()));
+                    let color = dt.apply(&self.data[y*self.w+x]);
+                    for &v in [color.r, color.g, color.b].iter() {
+                        wr.write_str(fmt!("%u ", (v*255.0 + 0.5) as uint));
                     }
                 }
             }
         }
     }
 
+    /// PNG with 8 or 16 bits per channel, sRGB encoded.
+    pub fn to_png(&self, bit_depth: uint, dt: &DisplayTransform) -> ~[u8] {
+        let max = ((1u << bit_depth) - 1) as float;
+        let mut samples = ~[];
+        for y in iterator::range(0, self.h) {
+            for x in iterator::range(0, self.w) {
+                let color = dt.apply(&self.data[y*self.w+x]);
+                for &v in [color.r, color.g, color.b].iter() {
+                    samples.push((v * max + 0.5) as u16);
+                }
+            }
+        }
+        png::encode(self.w, self.h, bit_depth, samples)
+    }
+
+    /// Portable float map holding the unclamped linear radiance.
+    pub fn to_pfm(&self) -> ~[u8] {
+        hdr::encode_pfm(self.w, self.h, self.data)
+    }
+
+    /// Radiance .hdr (RGBE) holding the unclamped linear radiance.
+    pub fn to_hdr(&self) -> ~[u8] {
+        hdr::encode_rgbe(self.w, self.h, self.data)
+    }
+
     pub fn each_coordinate(&self, f: &fn(x: uint, y: uint) -> bool) {
         for x in iterator::range(0, self.w) {
             for y in iterator::range(0, self.h) {
@@ -0,0 +1,149 @@
+use nalgebra::vec::*;
+use std::float;
+use scene;
+use random;
+use sampler::Sampler;
+use aabb;
+
+type Vec3f = Vec3<float>;
+
+/// A point sampled on the surface of an emitter, in world space.
+pub struct LightSample {
+    point: Vec3f,
+    normal: Vec3f,
+    pdf_area: float,
+    /// The triangle sampled, for meshes.
+    prim: uint
+}
+
+/// The emissive objects of a scene with non-zero area, referenced by index
+/// into `Scene::objects`.
+pub struct Lights {
+    indices: ~[uint]
+}
+
+impl Lights {
+    pub fn new(objs: &[scene::Object]) -> Lights {
+        let mut indices = ~[];
+        for (i, obj) in objs.iter().enumerate() {
+            if !obj.material.emission.is_black() && area(obj) > 0.0 {
+                indices.push(i);
+            }
+        }
+        Lights { indices: indices }
+    }
+
+    pub fn len(&self) -> uint {
+        self.indices.len()
+    }
+
+    /// Probability density, with respect to world space surface area, of
+    /// `sample` choosing a given point on `obj` with world space unit normal
+    /// `normal`. Points are chosen uniformly in object space, so the density
+    /// varies over objects with non-uniform scale or shear.
+    pub fn pdf_area(&self, obj: &scene::Object, normal: &Vec3f) -> float {
+        let area = area(obj);
+        if self.indices.len() == 0 || obj.material.emission.is_black() || !(area > 0.0) {
+            return 0.0;
+        }
+        1.0 / ((self.indices.len() as float) * area * obj.area_scale(normal))
+    }
+
+    /// Picks an emitter uniformly and a point uniformly on its surface.
+    pub fn sample<'a>(&self, objs: &'a [scene::Object], sampler: &mut Sampler)
+        -> Option<(&'a scene::Object, LightSample)>
+    {
+        if self.indices.len() == 0 {
+            return None;
+        }
+        let mut n = (sampler.next_1d() * (self.indices.len() as float)) as uint;
+        if n >= self.indices.len() { n = self.indices.len() - 1 }
+        let obj = &objs[self.indices[n]];
+
+        let (p, normal, prim) = sample_local(obj, sampler);
+        let normal = obj.to_world_normal(&normal);
+        Some((obj, LightSample {
+            point: obj.transform.transform(&p),
+            normal: normal,
+            pdf_area: self.pdf_area(obj, &normal),
+            prim: prim
+        }))
+    }
+}
+
+/// Surface area in object space.
+fn area(obj: &scene::Object) -> float {
+    match obj.shape {
+        scene::Sphere { radius } => 4.0 * float::consts::pi * radius * radius,
+        scene::Box { aabb: aabb::AABB { min, max } } => {
+            let d = max - min;
+            2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+        },
+        scene::Triangle { a, b, c, _ } => 0.5 * (b - a).cross(&(c - a)).norm(),
+        scene::Mesh { mesh: ref mesh } => mesh.get().area()
+    }
+}
+
+/// Uniformly distributed point on a triangle and the triangle's normal.
+fn sample_triangle(a: &Vec3f, b: &Vec3f, c: &Vec3f, sampler: &mut Sampler) -> (Vec3f, Vec3f) {
+    let (u, v) = sampler.next_2d();
+    let su = u.sqrt();
+    let (b0, b1) = (1.0 - su, su * (1.0 - v));
+    (*a * b0 + *b * b1 + *c * (1.0 - b0 - b1),
+     (*b - *a).cross(&(*c - *a)).normalized())
+}
+
+/// Uniformly distributed point on the surface and its normal, in object
+/// space, and the triangle it lies on for meshes.
+fn sample_local(obj: &scene::Object, sampler: &mut Sampler) -> (Vec3f, Vec3f, uint) {
+    match obj.shape {
+        scene::Sphere { radius } => {
+            let (u, v) = sampler.next_2d();
+            let n = random::uniform_sphere(u, v);
+            (n * radius, n, 0)
+        },
+        scene::Box { aabb: aabb::AABB { min, max } } => {
+            let d = max - min;
+            let areas = [d.y * d.z, d.x * d.z, d.x * d.y];
+            let total = areas[0] + areas[1] + areas[2];
+            // pick a pair of opposite faces by area, then one of the pair
+            let r = sampler.next_1d() * total * 2.0;
+            let far = r >= total;
+            let r = if far { r - total } else { r };
+            let (u, v) = sampler.next_2d();
+
+            let (p, n) = if r < areas[0] {
+                let x = if far { max.x } else { min.x };
+                (Vec3::new(x, min.y + u * d.y, min.z + v * d.z),
+                 Vec3::new(if far { 1.0 } else { -1.0 }, 0.0, 0.0))
+            } else if r < areas[0] + areas[1] {
+                let y = if far { max.y } else { min.y };
+                (Vec3::new(min.x + u * d.x, y, min.z + v * d.z),
+                 Vec3::new(0.0, if far { 1.0 } else { -1.0 }, 0.0))
+            } else {
+                let z = if far { max.z } else { min.z };
+                (Vec3::new(min.x + u * d.x, min.y + v * d.y, z),
+                 Vec3::new(0.0, 0.0, if far { 1.0 } else { -1.0 }))
+            };
+            (p, n, 0)
+        },
+        scene::Triangle { a, b, c, _ } => {
+            let (p, n) = sample_triangle(&a, &b, &c, sampler);
+            (p, n, 0)
+        },
+        scene::Mesh { mesh: ref mesh } => {
+            let mesh = mesh.get();
+            let tri = mesh.pick_by_area(sampler.next_1d());
+            let (a, b, c) = mesh.corners(tri);
+            let (p, n) = sample_triangle(&a, &b, &c, sampler);
+            (p, n, tri)
+        }
+    }
+}
+
+/// Power heuristic weight for a sample from a strategy with density `pdf_a`
+/// combined with one of density `pdf_b`.
+pub fn mis_weight(pdf_a: float, pdf_b: float) -> float {
+    let (a2, b2) = (pdf_a * pdf_a, pdf_b * pdf_b);
+    if a2 + b2 == 0.0 { 0.0 } else { a2 / (a2 + b2) }
+}
@@ -0,0 +1,4 @@
+/// Unwraps an `Ok` value, or returns the error from the enclosing function.
+macro_rules! try(
+    ($e:expr) => (match $e { Ok(v) => v, Err(e) => return Err(e) })
+)
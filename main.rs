@@ -1,4 +1,5 @@
-use std::{io, iterator, path};
+use std::{io, iterator, path, os, float};
+use std::ascii::StrAsciiExt;
 use image::{Image, RGB};
 use scene;
 use camera;
@@ -8,89 +9,195 @@ use nalgebra::adaptors::rotmat::Rotmat;
 use random;
 use extra::arc;
 use std::{task, comm};
+use sdlui;
 use sdlui::UI;
-use std::num::One;
+use transform::Affine;
 use obj;
 use aabb;
+use scenefile;
+use bvh;
+use light;
+use tonemap;
+use sampler;
+use sampler::Sampler;
+use bsdf;
+use texture;
+use texture::Constant;
+use cli;
+use extra::time;
 
 use extra::serialize::*;
 use extra::json;
 
 pub struct RenderOptions {
     width: uint,
-    height: uint
+    height: uint,
+    samples: Option<uint>,
+    workers: uint,
+    time_limit: Option<float>,
+    seed: u64,
+    headless: bool,
+    display: tonemap::DisplayTransform,
+    sampler: sampler::SamplerKind,
+    hemisphere: random::HemisphereSampling
 }
 
-fn trace_ray<S: scene::Scene>(ray: scene::Ray, scene: &S, depth: uint)
+impl RenderOptions {
+    pub fn new(width: uint, height: uint) -> RenderOptions {
+        RenderOptions {
+            width: width,
+            height: height,
+            samples: None,
+            workers: 8,
+            time_limit: None,
+            seed: 0,
+            headless: false,
+            display: tonemap::DisplayTransform::new(),
+            sampler: sampler::IndependentSampler,
+            hemisphere: random::CosineWeighted
+        }
+    }
+}
+
+/// State shared by all paths traced for one frame.
+struct TraceContext<'self, S> {
+    scene: &'self S,
+    lights: light::Lights,
+    hemisphere: random::HemisphereSampling
+}
+
+/// Light reflected towards `wo` from one randomly sampled emitter, with the
+/// multiple importance sampling weight against BSDF sampling applied.
+fn sample_direct<S: scene::Scene>(ctx: &TraceContext<S>, intr: &scene::Intersection, frame: &bsdf::Frame,
+                                  wo: &Vec3<float>, material: &scene::Material, sh: &bsdf::Shading,
+                                  sampler: &mut Sampler) -> RGB
+{
+    let (light_obj, ls) = match ctx.lights.sample(ctx.scene.objects(), sampler) {
+        Some(s) => s,
+        None => return RGB::black()
+    };
+
+    let to_light = ls.point - intr.position;
+    let dist = to_light.norm();
+    let dir = to_light * (1.0 / dist);
+    let wi = frame.to_local(&dir);
+    let cos_light = ls.normal.dot(&dir).abs();
+    let f = material.eval(sh, wo, &wi);
+    if f.is_black() || cos_light == 0.0 {
+        return RGB::black();
+    }
+
+    if ctx.scene.intersect(&intr.spawn_ray_to(&ls.point)).is_some() {
+        return RGB::black();
+    }
+
+    let pdf_light = ls.pdf_area * dist * dist / cos_light;
+    let pdf_bsdf = material.pdf(sh, wo, &wi);
+    let emission = light_obj.material.emission.eval(&light_obj.tex_point(ls.point, ls.prim));
+    emission.mul_v(&f).mul_t(
+        wi.z.abs() * light::mis_weight(pdf_light, pdf_bsdf) / pdf_light)
+}
+
+/// `bsdf_pdf` is the solid angle density with which the previous bounce
+/// chose `ray`, or None if it was a camera ray or a delta lobe.
+/// Emission found by rays from non-delta bounces is weighted against the
+/// direct light sampling done at that bounce.
+fn trace_ray<S: scene::Scene>(ctx: &TraceContext<S>, ray: scene::Ray, sampler: &mut Sampler,
+                              depth: uint, bsdf_pdf: Option<float>)
     -> RGB
 {
-    let maybe_intr = scene.intersect(&ray);
+    let maybe_intr = ctx.scene.intersect(&ray);
     let intr = match maybe_intr {
         None => return RGB::black(),
         Some(_) => maybe_intr.unwrap()
     };
 
+    let normal = intr.shading_normal;
+    let tp = intr.tex_point();
+    let material = &intr.object.material;
+
+    let emission = material.emission.eval(&tp);
+    let emitted = match bsdf_pdf {
+        Some(pdf) if !emission.is_black() => {
+            let cos_light = intr.geometric_normal.dot(&ray.dir).abs();
+            let pdf_light = ctx.lights.pdf_area(intr.object, &intr.geometric_normal) * intr.distance * intr.distance / cos_light;
+            emission.mul_t(light::mis_weight(pdf, pdf_light))
+        },
+        _ => emission
+    };
+
     // russian roulette
-    let mut color = intr.object.material.color;
+    let mut color = material.color.eval(&tp);
     let refls = [color.r, color.g, color.b];
     let max_refl_comp = *refls.iter().max().unwrap();
     if depth > 5 || max_refl_comp == 0.0 {
-        if random::random_real() < max_refl_comp {
+        if sampler.next_1d() < max_refl_comp {
             color = color.mul_t(1.0 / max_refl_comp);
         } else {
-            return intr.object.material.emission;
+            return emitted;
         }
     }
 
-    let rf = intr.object.material.rfd.sample();
+    let sh = bsdf::Shading {
+        albedo: color,
+        hemisphere: ctx.hemisphere,
+        roughness: material.roughness.map(|r| r.eval_float(&tp))
+    };
+    let frame = bsdf::Frame::from_normal_tangent(&normal, &intr.dpdu);
+    let wo = frame.to_local(&-ray.dir);
 
-    match rf {
-        scene::Diffuse => {
-            let hit_pt = ray.pos + ray.dir * intr.distance;
-            let normal = intr.object.normal_at(hit_pt);
+    let direct = if material.has_non_delta() {
+        sample_direct(ctx, &intr, &frame, &wo, material, &sh, sampler)
+    } else {
+        RGB::black()
+    };
 
-            let mut new_dir = random::random_vec();
-            if new_dir.dot(&normal) < 0.0 {
-                new_dir = -new_dir;
-            }
+    let u = sampler.next_2d();
+    let uc = sampler.next_1d();
+    let bs = match material.sample(&sh, &wo, u, uc) {
+        Some(bs) => bs,
+        None => return emitted.add_v(&direct)
+    };
 
-            let new_ray = scene::Ray { pos: hit_pt + new_dir * 0.001, dir: new_dir };
+    let new_dir = frame.to_world(&bs.wi);
+    let new_ray = intr.spawn_ray(&new_dir);
+    let indirect = trace_ray(ctx, new_ray, sampler, depth+1,
+                             if bs.delta { None } else { Some(bs.pdf) });
 
-            return intr.object.material.emission.add_v(
-                &color.mul_v(&trace_ray(new_ray, scene, depth+1)));
-        },
-        scene::Specular => {
-            let hit_pt = ray.pos + ray.dir * intr.distance;
-            let normal = intr.object.normal_at(hit_pt);
-            let new_dir = ray.dir - normal * 2.0 * normal.dot(&ray.dir);
-            let new_ray = scene::Ray {
-                pos: hit_pt + new_dir * 0.001,
-                dir: new_dir
-            };
-            return intr.object.material.emission.add_v(
-                &color.mul_v(&trace_ray(new_ray, scene, depth+1)));
-        }
-    }
+    emitted.add_v(&direct).add_v(&indirect.mul_v(&bs.f).mul_t(bs.wi.z.abs() / bs.pdf))
 }
 
-fn trace_pixel<S: scene::Scene>(x: float, y: float, camera: &camera::Camera, scene: &S)
+fn trace_pixel<S: scene::Scene>(ctx: &TraceContext<S>, x: float, y: float,
+                                camera: &camera::Camera, sampler: &mut Sampler)
     -> RGB
 {
     let ray = camera.make_ray(x, y);
-    trace_ray(ray, scene, 0)
+    trace_ray(ctx, ray, sampler, 0, None)
 }
 
-fn trace_image<S: scene::Scene>(opts: &RenderOptions, camera: &camera::Camera, scene: &S)
+/// Traces one sample per pixel. The random numbers used depend only on the
+/// seed, the pixel and `sample`.
+fn trace_image<S: scene::Scene>(opts: &RenderOptions, camera: &camera::Camera, scene: &S,
+                                sample: uint)
     -> Image
 {
+    let ctx = TraceContext {
+        scene: scene,
+        lights: light::Lights::new(scene.objects()),
+        hemisphere: opts.hemisphere
+    };
+    let mut pixel_sampler = sampler::new(opts.sampler, opts.seed, opts.samples.unwrap_or(1));
     let mut i = Image::new(opts.width, opts.height);
     for x in iterator::range(0, opts.width) {
         for y in iterator::range(0, opts.height) {
-            let jitter_x = (random::random_real() - 0.5) / (opts.width as float);
-            let jitter_y = (random::random_real() - 0.5) / (opts.width as float);
-            let color = trace_pixel(x as float / (opts.width as float) + jitter_x,
+            pixel_sampler.start_sample(y * opts.width + x, sample);
+            let (u, v) = pixel_sampler.next_2d();
+            let jitter_x = (u - 0.5) / (opts.width as float);
+            let jitter_y = (v - 0.5) / (opts.height as float);
+            let color = trace_pixel(&ctx,
+                                    x as float / (opts.width as float) + jitter_x,
                                     y as float / (opts.height as float) + jitter_y,
-                                    camera, scene);
+                                    camera, &mut *pixel_sampler);
             i.set(x, y, color);
         }
     }
@@ -98,88 +205,229 @@ fn trace_image<S: scene::Scene>(opts: &RenderOptions, camera: &camera::Camera, s
 }
 
 fn id() -> scene::Transform3d {
-    One::one()
+    Affine::identity()
 }
 
-pub fn entrypoint() {
-    let opts = RenderOptions {
-        width: 240,
-        height: 180
-    };
+fn builtin_scene() -> (scene::LinearScene, camera::Camera, RenderOptions) {
+    let opts = RenderOptions::new(240, 180);
     let mut scene = scene::LinearScene {
         objs: ~[
             scene::Object::new(id().translated(&Vec3::new(0.0, -1002.0, 0.0)),
                                scene::Sphere { radius: 1000.0 },
-                               scene::Material { rfd: scene::ReflectanceDistribution { diffuse: 1.0, specular: 0.0 },
-                                                 color: RGB { r: 0.3, g: 0.3, b: 0.3 }, emission: RGB::black() }),
+                               scene::Material::diffuse(texture::Procedural { pattern: texture::Checker,
+                                                                              space: texture::ObjectSpace,
+                                                                              frequency: 1.0,
+                                                                              low: RGB { r: 0.35, g: 0.35, b: 0.35 },
+                                                                              high: RGB { r: 0.2, g: 0.2, b: 0.2 } },
+                                                        Constant(RGB::black()))),
             scene::Object::new(id().translated(&Vec3::new(0.0, 0.0, -200.0)),
                                scene::Box { aabb: aabb::AABB { min: Vec3::new(-100.0, -100.0, 0.0), max: Vec3::new(100.0, 100.0, 0.1) } },
-                               scene::Material::diffuse(RGB::black(), RGB { r: 10.0, g: 10.0, b: 10.0 })),
+                               scene::Material::diffuse(Constant(RGB::black()), Constant(RGB { r: 10.0, g: 10.0, b: 10.0 }))),
             scene::Object::new(id().rotated(&Vec3::new(0.0, -2.0, 0.0)).translated(&Vec3::new( 1.5, -2.0, 0.0)),
                                scene::Box { aabb: aabb::AABB { min: Vec3::new(-0.5, 0.0, -0.5),
                                                                max: Vec3::new( 0.5, 1.0,  0.5) } },
-                               scene::Material { rfd: scene::ReflectanceDistribution {
-                                                          diffuse: 0.2, specular: 0.8
-                                                      },
-                                                 color: RGB::red(), emission: RGB::black() }),
+                               scene::Material::from_weights(0.2, 0.8, 0.0, 1.0, Constant(RGB::red()), Constant(RGB::black()))),
             scene::Object::new(id().translated(&Vec3::new(-1.5, -1.0, 0.0)),
                                scene::Sphere { radius: 1.0 },
-                               scene::Material { rfd: scene::ReflectanceDistribution {
-                                                          diffuse: 0.3, specular: 0.7
-                                                      },
-                                                 color: RGB::white(), emission: RGB::black() }),
+                               scene::Material::from_weights(0.3, 0.7, 0.0, 1.0, Constant(RGB::white()), Constant(RGB::black()))),
             scene::Object::new(id().translated(&Vec3::new(0.0, -2.0, 10.0)),
                                scene::Box { aabb: aabb::AABB { min: Vec3::new(-15.0, 0.0, 0.0), max: Vec3::new(15.0, 30.0, 0.1) } },
-                               scene::Material { rfd: scene::ReflectanceDistribution { diffuse: 0.1, specular: 0.9 }, color: RGB::white(), emission: RGB::black() }),
+                               scene::Material::from_weights(0.1, 0.9, 0.0, 1.0, Constant(RGB::white()), Constant(RGB::black()))),
                                /*
             scene::Object::new(id().translated(&Vec3::new(-2.0, 0.0, 0.0)),
-                               scene::Triangle { a: Vec3::new(-1.0, 0.0, 0.0), b: Vec3::new(0.0, 1.0, 0.0), c: Vec3::new(1.0, 0.0, 0.0) },
-                               scene::Material::diffuse(RGB::blue(), RGB::black()))
+                               scene::Triangle { a: Vec3::new(-1.0, 0.0, 0.0), b: Vec3::new(0.0, 1.0, 0.0), c: Vec3::new(1.0, 0.0, 0.0),
+                                                 uvs: scene::DEFAULT_TRIANGLE_UVS },
+                               scene::Material::diffuse(Constant(RGB::blue()), Constant(RGB::black())))
                                */
         ]
     };
 
-//    obj::load_obj(&path::Path("dragon.obj"), &mut scene);
+//    obj::load_obj(&path::Path("dragon.obj"), &id(), &scene::Material::diffuse(Constant(RGB { r: 0.75, g: 0.75, b: 0.75 }), Constant(RGB::black())), &mut scene).unwrap();
 
     let camera = camera::Camera::new(Vec3::new(-2.0, 2.5, -3.0),
                                      Vec3::new(0.0, 0.0,  0.0),
                                      1.57, (opts.width as float)/(opts.height as float));
 
+    (scene, camera, opts)
+}
+
+/// Blends finished frames into `image` in sample order, so the floating
+/// point result does not depend on the order in which tasks finish.
+fn blend_ready(pending: &mut ~[(uint, Image)], image: &mut Image, done: &mut uint) {
+    loop {
+        match pending.iter().position(|&(sample, _)| sample == *done) {
+            Some(i) => {
+                let (_, frame) = pending.swap_remove(i);
+                frame.blend_into(image, *done);
+                *done += 1;
+            }
+            None => break
+        }
+    }
+}
+
+fn render<S: scene::Scene + Send + Freeze>(scene: S, camera: camera::Camera, opts: RenderOptions)
+    -> Image
+{
     let mut done = 0u;
+    let mut spawned = 0u;
+    let mut pending = ~[];
 
     let mut image = Image::new(opts.width, opts.height);
 
-    let mut ui = UI::new(&opts);
+    let mut ui = if opts.headless { None } else { Some(UI::new(&opts)) };
 
     let scene_rc = arc::Arc::new(scene);
     let camera_rc = arc::Arc::new(camera);
 
-    let mut tasks_running = 0u;
+    let start_time = time::precise_time_s();
     let (data_port, data_chan) = comm::stream();
     let data_chan = comm::SharedChan::new(data_chan);
     loop {
-        while tasks_running < 8 {
+        while spawned - done - pending.len() < opts.workers &&
+              opts.samples.map_default(true, |&n| spawned < n) {
             let my_chan = data_chan.clone();
             let (my_scene, my_camera) = (scene_rc.clone(), camera_rc.clone());
-            tasks_running += 1;
+            let sample = spawned;
+            spawned += 1;
             do task::spawn_sched(task::SingleThreaded) {
-                let frame = trace_image(&opts, my_camera.get(), my_scene.get());
-                my_chan.send(frame);
+                let frame = trace_image(&opts, my_camera.get(), my_scene.get(), sample);
+                my_chan.send((sample, frame));
             }
         }
 
-        let frame = data_port.recv();
-        frame.blend_into(&mut image, done);
-        done += 1;
-        tasks_running -= 1;
-        printf!("\r%5u frames done", done);
-        if done % 10 == 0 && !ui.paint(image.data) {
+        pending.push(data_port.recv());
+        let before = done;
+        blend_ready(&mut pending, &mut image, &mut done);
+        if done != before {
+            printf!("\r%5u frames done, %.1fs", done, time::precise_time_s() - start_time);
+        }
+
+        if opts.samples.map_default(false, |&n| done >= n) {
+            break;
+        }
+        if opts.time_limit.map_default(false, |&t| time::precise_time_s() - start_time >= t) {
             break;
         }
+        match ui {
+            // frames that arrive ahead of a slow one still keep the window responsive
+            Some(ref mut ui) if done == before || done / 10 != before / 10 => {
+                if !ui.paint(image.data, &opts.display) { break; }
+            }
+            _ => ()
+        }
+    }
+
+    // the remaining tasks would fail sending to a closed port, so wait for
+    // them; their frames are as good as any other
+    while done < spawned {
+        pending.push(data_port.recv());
+        blend_ready(&mut pending, &mut image, &mut done);
     }
-    println("");
+    printfln!("\r%5u frames done, %.1fs", done, time::precise_time_s() - start_time);
 
-    let fp = io::file_writer(&path::Path("output.ppm"), [io::Create]).unwrap();
-    fp.write_str(image.to_ppm());
+    image
 }
 
+fn save_image(image: &Image, path: &path::Path, bit_depth: uint, dt: &tonemap::DisplayTransform)
+    -> Result<(), ~str>
+{
+    let ext = path.filetype().map_default(~"", |e| e.to_ascii_lower());
+    let data = match ext.as_slice() {
+        ".png" => image.to_png(bit_depth, dt),
+        ".pfm" => image.to_pfm(),
+        ".hdr" => image.to_hdr(),
+        ".ppm" | "" => image.to_ppm(dt).as_bytes().to_owned(),
+        _ => return Err(fmt!("%s: unsupported output format '%s'", path.to_str(), ext))
+    };
+    match io::file_writer(path, [io::Create, io::Truncate]) {
+        Ok(fp) => {
+            fp.write(data);
+            Ok(())
+        }
+        Err(e) => Err(fmt!("%s: %s", path.to_str(), e))
+    }
+}
+
+pub fn entrypoint() {
+    let argv = os::args();
+    let args = match cli::parse(argv.tail()) {
+        Ok(Some(a)) => a,
+        Ok(None) => {
+            print(cli::usage(argv[0]));
+            return;
+        }
+        Err(e) => {
+            io::stderr().write_line(e);
+            io::stderr().write_str(cli::usage(argv[0]));
+            os::set_exit_status(2);
+            return;
+        }
+    };
+
+    let (scene, mut camera, mut opts) = match args.scene {
+        Some(ref file) => match scenefile::load(&path::Path(*file)) {
+            Ok(desc) => (desc.scene, desc.camera, desc.options),
+            Err(e) => {
+                io::stderr().write_line(e);
+                os::set_exit_status(1);
+                return;
+            }
+        },
+        None => builtin_scene()
+    };
+
+    if args.width.is_some() || args.height.is_some() {
+        opts.width = args.width.unwrap_or(opts.width);
+        opts.height = args.height.unwrap_or(opts.height);
+        camera = camera::Camera::new(camera.position, camera.lookat, camera.fov,
+                                     (opts.width as float)/(opts.height as float));
+    }
+    if args.samples.is_some() { opts.samples = args.samples }
+    if args.time_limit.is_some() { opts.time_limit = args.time_limit }
+    opts.workers = args.workers.unwrap_or(opts.workers);
+    opts.seed = args.seed.unwrap_or(opts.seed);
+    opts.headless = args.headless;
+    if !opts.headless && !sdlui::available() {
+        io::stderr().write_line("built without SDL, rendering headless");
+        opts.headless = true;
+    }
+    if opts.headless && opts.samples.is_none() && opts.time_limit.is_none() {
+        io::stderr().write_line("headless rendering needs --samples or --time-limit");
+        os::set_exit_status(2);
+        return;
+    }
+    opts.sampler = args.sampler;
+    opts.hemisphere = args.hemisphere;
+    match (opts.sampler, opts.samples) {
+        (sampler::StratifiedSampler, None) => {
+            io::stderr().write_line("the stratified sampler needs --samples");
+            os::set_exit_status(2);
+            return;
+        }
+        _ => ()
+    }
+    opts.display.exposure = args.exposure;
+    opts.display.operator = args.tonemap;
+
+    let image = match args.accel {
+        cli::LinearAccel => render(scene, camera, opts),
+        cli::BVHAccel => {
+            let start = time::precise_time_s();
+            let bvh = bvh::BVHScene::new(scene.objs);
+            let stats = bvh.tree.stats();
+            printfln!("BVH built in %.2fs: %u nodes, %u leaves, depth %u, max %u objects per leaf",
+                      time::precise_time_s() - start, stats.nodes, stats.leaves,
+                      stats.depth, stats.max_leaf_prims);
+            render(bvh, camera, opts)
+        }
+    };
+
+    match save_image(&image, &path::Path(args.output), args.bit_depth, &opts.display) {
+        Ok(()) => (),
+        Err(e) => {
+            io::stderr().write_line(e);
+            os::set_exit_status(1);
+        }
+    }
+}
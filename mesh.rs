@@ -0,0 +1,178 @@
+use nalgebra::vec::*;
+use aabb::AABB;
+use bvh;
+use scene;
+
+type Vec3f = Vec3<float>;
+
+/// Indices of the corners of a triangle into the vertex buffers of its mesh.
+/// Normals and texture coordinates are optional per triangle.
+pub struct MeshTriangle {
+    positions: [uint, ..3],
+    normals: Option<[uint, ..3]>,
+    uvs: Option<[uint, ..3]>
+}
+
+/// A triangle mesh in object space. Vertex data is stored once and shared
+/// by the triangles indexing it, and a BVH over the triangles keeps
+/// intersection logarithmic in their number.
+pub struct TriangleMesh {
+    positions: ~[Vec3f],
+    normals: ~[Vec3f],
+    uvs: ~[(float, float)],
+    triangles: ~[MeshTriangle],
+    /// Running sums of triangle areas, for choosing triangles by area.
+    area_sums: ~[float],
+    tree: bvh::Tree
+}
+
+impl TriangleMesh {
+    /// Fails if a triangle indexes past the end of a buffer.
+    pub fn new(positions: ~[Vec3f], normals: ~[Vec3f], uvs: ~[(float, float)],
+               triangles: ~[MeshTriangle]) -> TriangleMesh {
+        for t in triangles.iter() {
+            assert!(t.positions.iter().all(|&i| i < positions.len()));
+            assert!(t.normals.map_default(true, |ns| ns.iter().all(|&i| i < normals.len())));
+            assert!(t.uvs.map_default(true, |ts| ts.iter().all(|&i| i < uvs.len())));
+        }
+
+        let mut boxes = ~[];
+        let mut area_sums = ~[];
+        let mut total = 0.0;
+        for t in triangles.iter() {
+            let (a, b, c) = (positions[t.positions[0]], positions[t.positions[1]],
+                             positions[t.positions[2]]);
+            let mut bounds = AABB::empty();
+            bounds.stretch_to_point(&a);
+            bounds.stretch_to_point(&b);
+            bounds.stretch_to_point(&c);
+            boxes.push(bounds);
+            total += 0.5 * (b - a).cross(&(c - a)).norm();
+            area_sums.push(total);
+        }
+
+        TriangleMesh {
+            tree: bvh::Tree::build(boxes),
+            positions: positions,
+            normals: normals,
+            uvs: uvs,
+            triangles: triangles,
+            area_sums: area_sums
+        }
+    }
+
+    pub fn len(&self) -> uint {
+        self.triangles.len()
+    }
+
+    pub fn corners(&self, tri: uint) -> (Vec3f, Vec3f, Vec3f) {
+        let t = &self.triangles[tri];
+        (self.positions[t.positions[0]], self.positions[t.positions[1]], self.positions[t.positions[2]])
+    }
+
+    /// Texture coordinates at the corners of a triangle, or the defaults of
+    /// `scene::Triangle` if it has none.
+    pub fn corner_uvs(&self, tri: uint) -> [(float, float), ..3] {
+        match self.triangles[tri].uvs {
+            Some(i) => [self.uvs[i[0]], self.uvs[i[1]], self.uvs[i[2]]],
+            None => scene::DEFAULT_TRIANGLE_UVS
+        }
+    }
+
+    /// The vertex normals of a triangle blended by barycentric coordinates
+    /// `(b0, b1, b2)`, if it has vertex normals.
+    pub fn interpolated_normal(&self, tri: uint, (b0, b1, b2): (float, float, float)) -> Option<Vec3f> {
+        match self.triangles[tri].normals {
+            Some(i) => {
+                let n = self.normals[i[0]] * b0 + self.normals[i[1]] * b1 + self.normals[i[2]] * b2;
+                if n.norm() > 0.0 { Some(n.normalized()) } else { None }
+            },
+            None => None
+        }
+    }
+
+    /// The closest triangle hit by an object-space ray within its interval,
+    /// and the distance to it.
+    pub fn intersect(&self, ray: &scene::Ray) -> Option<(uint, float)> {
+        let mut closest = None;
+        do self.tree.traverse(ray) |i, t_max| {
+            let (a, b, c) = self.corners(i);
+            match scene::intersect_triangle(&a, &b, &c, &ray.clipped(t_max)) {
+                Some(t) => {
+                    closest = Some((i, t));
+                    Some(t)
+                }
+                None => None
+            }
+        }
+        closest
+    }
+
+    pub fn area(&self) -> float {
+        if self.area_sums.len() == 0 { 0.0 } else { self.area_sums[self.area_sums.len() - 1] }
+    }
+
+    /// Picks a triangle with probability proportional to its area, given `u`
+    /// in [0, 1). Fails if the mesh is empty.
+    pub fn pick_by_area(&self, u: float) -> uint {
+        assert!(self.area_sums.len() > 0);
+        let target = u * self.area();
+        let (mut lo, mut hi) = (0u, self.area_sums.len() - 1);
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            if self.area_sums[mid] <= target { lo = mid + 1 } else { hi = mid }
+        }
+        lo
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{TriangleMesh, MeshTriangle};
+    use nalgebra::vec::*;
+    use scene;
+    use std::iterator;
+
+    static N: uint = 10;
+    static CELL: float = 5e-4;
+
+    /// An N by N grid of half-millimetre squares in the plane z = 0, two
+    /// triangles each, like a patch of a dense scan.
+    fn grid() -> TriangleMesh {
+        let mut positions = ~[];
+        for y in iterator::range(0, N + 1) {
+            for x in iterator::range(0, N + 1) {
+                positions.push(Vec3::new(x as float * CELL, y as float * CELL, 0.0));
+            }
+        }
+        let mut triangles = ~[];
+        for y in iterator::range(0, N) {
+            for x in iterator::range(0, N) {
+                let i = y * (N + 1) + x;
+                triangles.push(MeshTriangle { positions: [i, i + 1, i + N + 2], normals: None, uvs: None });
+                triangles.push(MeshTriangle { positions: [i, i + N + 2, i + N + 1], normals: None, uvs: None });
+            }
+        }
+        TriangleMesh::new(positions, ~[], ~[], triangles)
+    }
+
+    #[test]
+    fn sub_millimetre_triangles_are_hit() {
+        let mesh = grid();
+        for y in iterator::range(0, N) {
+            for x in iterator::range(0, N) {
+                // a quarter cell in from the lower right corner, inside the first triangle
+                let p = Vec3::new((x as float + 0.75) * CELL, (y as float + 0.25) * CELL, -1.0);
+                let ray = scene::Ray::new(p, Vec3::new(0.0, 0.0, 1.0));
+                match mesh.intersect(&ray) {
+                    Some((tri, t)) => {
+                        assert_eq!(tri, 2 * (y * N + x));
+                        assert!((t - 1.0).abs() < 1e-9);
+                    }
+                    None => fail!(fmt!("missed cell (%u, %u)", x, y))
+                }
+            }
+        }
+        assert!((mesh.area() - (N * N) as float * CELL * CELL).abs() < 1e-15);
+    }
+}
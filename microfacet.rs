@@ -0,0 +1,113 @@
+use nalgebra::vec::*;
+use std::float;
+
+type Vec3f = Vec3<float>;
+
+/// The Trowbridge-Reitz (GGX) microfacet distribution with Smith's
+/// height-correlated masking-shadowing. Directions are in the local shading
+/// frame with +z the macrosurface normal; `alpha_x` is the roughness along the
+/// frame's first tangent.
+#[deriving(Clone)]
+pub struct TrowbridgeReitz {
+    alpha_x: float,
+    alpha_y: float
+}
+
+static MIN_ALPHA: float = 1e-4;
+/// Textured roughness never makes a distribution smooth, since whether a lobe
+/// is a delta distribution must not vary over a surface.
+static MIN_TEXTURED_ROUGHNESS: float = 0.05;
+
+impl TrowbridgeReitz {
+    pub fn new(alpha_x: float, alpha_y: float) -> TrowbridgeReitz {
+        let a = |a: float| if a < MIN_ALPHA { MIN_ALPHA } else { a };
+        TrowbridgeReitz { alpha_x: a(alpha_x), alpha_y: a(alpha_y) }
+    }
+
+    /// Perceptually linear roughness in [0, 1] mapped to alpha = roughness^2.
+    pub fn from_roughness(roughness_u: float, roughness_v: float) -> TrowbridgeReitz {
+        TrowbridgeReitz::new(roughness_u * roughness_u, roughness_v * roughness_v)
+    }
+
+    /// The distribution at a shading point, where `roughness` from a
+    /// texture replaces the lobe's own, isotropically. Smooth distributions
+    /// stay smooth.
+    pub fn at(&self, roughness: Option<float>) -> TrowbridgeReitz {
+        match roughness {
+            Some(r) if !self.effectively_smooth() => {
+                let r = if r < MIN_TEXTURED_ROUGHNESS { MIN_TEXTURED_ROUGHNESS }
+                        else if r > 1.0 { 1.0 }
+                        else { r };
+                TrowbridgeReitz::from_roughness(r, r)
+            },
+            _ => self.clone()
+        }
+    }
+
+    /// Density of microfacet normals `wm`, with respect to projected area.
+    pub fn d(&self, wm: &Vec3f) -> float {
+        let z2 = wm.z * wm.z;
+        if z2 <= 0.0 { return 0.0 }
+        let e = (wm.x * wm.x / (self.alpha_x * self.alpha_x) +
+                 wm.y * wm.y / (self.alpha_y * self.alpha_y)) / z2;
+        1.0 / (float::consts::pi * self.alpha_x * self.alpha_y * z2 * z2 * (1.0 + e) * (1.0 + e))
+    }
+
+    pub fn lambda(&self, w: &Vec3f) -> float {
+        let z2 = w.z * w.z;
+        if z2 <= 0.0 { return float::infinity }
+        let alpha2_tan2 = (w.x * w.x * self.alpha_x * self.alpha_x +
+                           w.y * w.y * self.alpha_y * self.alpha_y) / z2;
+        ((1.0 + alpha2_tan2).sqrt() - 1.0) * 0.5
+    }
+
+    pub fn g1(&self, w: &Vec3f) -> float {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    pub fn g(&self, wo: &Vec3f, wi: &Vec3f) -> float {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    /// Density of the normals visible from `w`.
+    pub fn d_visible(&self, w: &Vec3f, wm: &Vec3f) -> float {
+        let cos = w.z.abs();
+        if cos == 0.0 { return 0.0 }
+        self.g1(w) / cos * self.d(wm) * w.dot(wm).abs()
+    }
+
+    /// Samples a normal visible from `w` (which must have w.z > 0), after
+    /// Heitz, "Sampling the GGX Distribution of Visible Normals".
+    pub fn sample_visible(&self, w: &Vec3f, u: (float, float)) -> Vec3f {
+        let (u1, u2) = u;
+        let wh = Vec3::new(self.alpha_x * w.x, self.alpha_y * w.y, w.z).normalized();
+
+        let lensq = wh.x * wh.x + wh.y * wh.y;
+        let t1 = if lensq > 0.0 {
+            Vec3::new(-wh.y, wh.x, 0.0) * (1.0 / lensq.sqrt())
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let t2 = wh.cross(&t1);
+
+        let r = u1.sqrt();
+        let phi = 2.0 * float::consts::pi * u2;
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + wh.z);
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * r * phi.sin();
+        let pz = 1.0 - p1 * p1 - p2 * p2;
+        let nh = t1 * p1 + t2 * p2 + wh * (if pz > 0.0 { pz.sqrt() } else { 0.0 });
+
+        let z = if nh.z > 1e-6 { nh.z } else { 1e-6 };
+        Vec3::new(self.alpha_x * nh.x, self.alpha_y * nh.y, z).normalized()
+    }
+
+    pub fn effectively_smooth(&self) -> bool {
+        self.alpha_x < 1e-3 && self.alpha_y < 1e-3
+    }
+}
+
+/// Reflects `wo` about the normal `n`.
+pub fn reflect(wo: &Vec3f, n: &Vec3f) -> Vec3f {
+    -*wo + *n * (2.0 * wo.dot(n))
+}
@@ -0,0 +1,70 @@
+use nalgebra::vec::*;
+use std::iterator;
+use image::lerp;
+
+type Vec3f = Vec3<float>;
+
+/// Ken Perlin's reference permutation.
+static PERM: [u8, ..256] = [
+    151, 160, 137, 91, 90, 15, 131, 13, 201, 95, 96, 53, 194, 233, 7, 225, 140, 36, 103, 30, 69,
+    142, 8, 99, 37, 240, 21, 10, 23, 190, 6, 148, 247, 120, 234, 75, 0, 26, 197, 62, 94, 252, 219,
+    203, 117, 35, 11, 32, 57, 177, 33, 88, 237, 149, 56, 87, 174, 20, 125, 136, 171, 168, 68, 175,
+    74, 165, 71, 134, 139, 48, 27, 166, 77, 146, 158, 231, 83, 111, 229, 122, 60, 211, 133, 230,
+    220, 105, 92, 41, 55, 46, 245, 40, 244, 102, 143, 54, 65, 25, 63, 161, 1, 216, 80, 73, 209, 76,
+    132, 187, 208, 89, 18, 169, 200, 196, 135, 130, 116, 188, 159, 86, 164, 100, 109, 198, 173,
+    186, 3, 64, 52, 217, 226, 250, 124, 123, 5, 202, 38, 147, 118, 126, 255, 82, 85, 212, 207, 206,
+    59, 227, 47, 16, 58, 17, 182, 189, 28, 42, 223, 183, 170, 213, 119, 248, 152, 2, 44, 154, 163,
+    70, 221, 153, 101, 155, 167, 43, 172, 9, 129, 22, 39, 253, 19, 98, 108, 110, 79, 113, 224, 232,
+    178, 185, 112, 104, 218, 246, 97, 228, 251, 34, 242, 193, 238, 210, 144, 12, 191, 179, 162,
+    241, 81, 51, 145, 235, 249, 14, 239, 107, 49, 192, 214, 31, 181, 199, 106, 157, 184, 84, 204,
+    176, 115, 121, 50, 45, 127, 4, 150, 254, 138, 236, 205, 93, 222, 114, 67, 29, 24, 72, 243, 141,
+    128, 195, 78, 66, 215, 61, 156, 180];
+
+fn perm(i: int) -> int {
+    PERM[(i & 255) as uint] as int
+}
+
+fn fade(t: float) -> float {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+/// Dot product of the offset with one of twelve edge directions of a cube.
+fn grad(hash: int, x: float, y: float, z: float) -> float {
+    let h = hash & 15;
+    let u = if h < 8 { x } else { y };
+    let v = if h < 4 { y } else if h == 12 || h == 14 { x } else { z };
+    (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+}
+
+/// Perlin's improved gradient noise, in roughly [-1, 1] and zero at integer
+/// lattice points.
+pub fn perlin(p: &Vec3f) -> float {
+    let (fx, fy, fz) = (p.x.floor(), p.y.floor(), p.z.floor());
+    let (xi, yi, zi) = (fx as int, fy as int, fz as int);
+    let (x, y, z) = (p.x - fx, p.y - fy, p.z - fz);
+    let (u, v, w) = (fade(x), fade(y), fade(z));
+
+    let a = perm(xi) + yi;
+    let (aa, ab) = (perm(a) + zi, perm(a + 1) + zi);
+    let b = perm(xi + 1) + yi;
+    let (ba, bb) = (perm(b) + zi, perm(b + 1) + zi);
+
+    let along_x = |i: int, j: int, dy: float, dz: float| {
+        lerp(grad(perm(i), x, y - dy, z - dz), grad(perm(j), x - 1.0, y - dy, z - dz), u)
+    };
+    lerp(lerp(along_x(aa, ba, 0.0, 0.0), along_x(ab, bb, 1.0, 0.0), v),
+         lerp(along_x(aa + 1, ba + 1, 0.0, 1.0), along_x(ab + 1, bb + 1, 1.0, 1.0), v),
+         w)
+}
+
+/// Sum of `octaves` octaves of absolute noise, each at twice the frequency
+/// and half the amplitude of the previous one.
+pub fn turbulence(p: &Vec3f, octaves: uint) -> float {
+    let mut sum = 0.0;
+    let mut scale = 1.0;
+    for _ in iterator::range(0, octaves) {
+        sum += perlin(&(*p * scale)).abs() / scale;
+        scale *= 2.0;
+    }
+    sum
+}
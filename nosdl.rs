@@ -0,0 +1,20 @@
+// Stand-in for sdlui when built with --cfg nosdl, for machines without
+// SDL2 or a display. There is no preview window.
+
+use image::RGB;
+use main::RenderOptions;
+use tonemap::DisplayTransform;
+
+pub struct UI;
+
+pub fn available() -> bool { false }
+
+impl UI {
+    pub fn new(_opts: &RenderOptions) -> UI {
+        fail!("rspt was built without SDL support");
+    }
+
+    pub fn paint(&mut self, _data: &[RGB], _dt: &DisplayTransform) -> bool {
+        true
+    }
+}
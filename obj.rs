@@ -1,32 +1,119 @@
 use scene;
-use image::RGB;
+use mesh;
+use extra::arc::Arc;
 use nalgebra::vec::*;
-use std::{path, io, float, uint};
+use std::{path, io, float, int, iterator};
 
-pub fn load_obj(path: &path::Path, scene: &mut scene::LinearScene) {
-    let mut vcache = ~[::std::num::Zero::zero()];
-    let rd = io::file_reader(path).unwrap();
+type Vec3f = Vec3<float>;
+
+/// Resolves a 1-based, or negative and relative to the end, OBJ index.
+fn resolve_index(s: &str, len: uint, ctx: &str) -> Result<uint, ~str> {
+    match int::from_str(s) {
+        Some(i) if i > 0 && (i as uint) <= len => Ok((i - 1) as uint),
+        Some(i) if i < 0 && ((-i) as uint) <= len => Ok(len - ((-i) as uint)),
+        _ => Err(fmt!("%s: invalid index '%s'", ctx, s))
+    }
+}
+
+fn parse_floats(words: &[&str], n: uint, ctx: &str) -> Result<~[float], ~str> {
+    if words.len() < n {
+        return Err(fmt!("%s: expected %u numbers", ctx, n));
+    }
+    let mut out = ~[];
+    for w in words.slice(0, n).iter() {
+        match float::from_str(*w) {
+            Some(f) => out.push(f),
+            None => return Err(fmt!("%s: invalid number '%s'", ctx, *w))
+        }
+    }
+    Ok(out)
+}
+
+/// Loads the faces of a Wavefront OBJ file as a single triangle mesh object,
+/// fanning out polygons with more than three vertices. Texture coordinates
+/// (`vt`) and normals (`vn`) are used when every corner of a face has one;
+/// groups and material libraries are ignored. A file without faces is an
+/// error.
+pub fn load_obj(path: &path::Path, transform: &scene::Transform3d, material: &scene::Material,
+                scene: &mut scene::LinearScene) -> Result<(), ~str> {
+    let rd = match io::file_reader(path) {
+        Ok(rd) => rd,
+        Err(e) => return Err(fmt!("%s: %s", path.to_str(), e))
+    };
+    let mut positions: ~[Vec3f] = ~[];
+    let mut uvs: ~[(float, float)] = ~[];
+    let mut normals: ~[Vec3f] = ~[];
+    let mut triangles = ~[];
+    let mut line_no = 0u;
 
     while !rd.eof() {
         let line = rd.read_line();
-        if line.len() == 0 { loop }
-        match line[0] as char {
-            'v' => {
-                let cs: ~[&str] = line.split_iter(' ').collect();
-                vcache.push(Vec3::new(float::from_str(cs[1]).unwrap(),
-                                      float::from_str(cs[2]).unwrap(),
-                                      float::from_str(cs[3]).unwrap()));
-            }
-            'f' => {
-                let is: ~[&str] = line.split_iter(' ').collect();
-                let tri = scene::Triangle { a: vcache[uint::from_str(is[1]).unwrap()],
-                                            b: vcache[uint::from_str(is[2]).unwrap()],
-                                            c: vcache[uint::from_str(is[3]).unwrap()] };
-                scene.objs.push(scene::Object { inv_transform: ::std::num::One::one(),
-                                                shape: tri,
-                                                material: scene::Material::diffuse(RGB { r: 0.75, g: 0.75, b: 0.75 }, RGB::black()) });
+        line_no += 1;
+        let ctx = fmt!("%s:%u", path.to_str(), line_no);
+        let words: ~[&str] = line.word_iter().collect();
+        if words.len() == 0 { loop }
+
+        match words[0] {
+            "v" => {
+                let p = try!(parse_floats(words.tail(), 3, ctx));
+                positions.push(Vec3::new(p[0], p[1], p[2]));
+            },
+            "vt" => {
+                let t = try!(parse_floats(words.tail(), 2, ctx));
+                uvs.push((t[0], t[1]));
+            },
+            "vn" => {
+                let n = try!(parse_floats(words.tail(), 3, ctx));
+                normals.push(Vec3::new(n[0], n[1], n[2]));
             },
-            _ => fail!("unsupported obj entry")
+            "f" => {
+                let mut corners = ~[];
+                for w in words.tail().iter() {
+                    let parts: ~[&str] = w.split_iter('/').collect();
+                    let v = try!(resolve_index(parts[0], positions.len(), ctx));
+                    let t = if parts.len() > 1 && parts[1].len() > 0 {
+                        Some(try!(resolve_index(parts[1], uvs.len(), ctx)))
+                    } else {
+                        None
+                    };
+                    let n = if parts.len() > 2 && parts[2].len() > 0 {
+                        Some(try!(resolve_index(parts[2], normals.len(), ctx)))
+                    } else {
+                        None
+                    };
+                    corners.push((v, t, n));
+                }
+                if corners.len() < 3 {
+                    return Err(fmt!("%s: a face needs at least three vertices", ctx));
+                }
+
+                for i in iterator::range(1, corners.len() - 1) {
+                    let (ia, ta, na) = corners[0];
+                    let (ib, tb, nb) = corners[i];
+                    let (ic, tc, nc) = corners[i + 1];
+                    triangles.push(mesh::MeshTriangle {
+                        positions: [ia, ib, ic],
+                        normals: match (na, nb, nc) {
+                            (Some(na), Some(nb), Some(nc)) => Some([na, nb, nc]),
+                            _ => None
+                        },
+                        uvs: match (ta, tb, tc) {
+                            (Some(ta), Some(tb), Some(tc)) => Some([ta, tb, tc]),
+                            _ => None
+                        }
+                    });
+                }
+            },
+            // comments, groups, smoothing and materials
+            _ => ()
         }
     }
+
+    if triangles.len() == 0 {
+        return Err(fmt!("%s: no faces", path.to_str()));
+    }
+    let mesh = mesh::TriangleMesh::new(positions, normals, uvs, triangles);
+    scene.objs.push(scene::Object::new(transform.clone(), scene::Mesh { mesh: Arc::new(mesh) },
+                                       material.clone()));
+    Ok(())
 }
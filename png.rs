@@ -0,0 +1,632 @@
+use std::{vec, iterator};
+
+static SIGNATURE: [u8, ..8] = [137, 80, 78, 71, 13, 10, 26, 10];
+/// LZ77 parameters: the deflate window, and how many earlier positions with
+/// the same three-byte hash are tried before settling for the best match.
+static WINDOW: uint = 32768;
+static HASH_BITS: uint = 15;
+static MAX_CHAIN: uint = 64;
+static MIN_MATCH: uint = 3;
+static MAX_MATCH: uint = 258;
+
+fn crc_table() -> ~[u32] {
+    do vec::from_fn(256) |n| {
+        let mut c = n as u32;
+        for _ in iterator::range(0, 8) {
+            c = if c & 1 != 0 { 0xedb88320 ^ (c >> 1) } else { c >> 1 };
+        }
+        c
+    }
+}
+
+fn crc32(table: &[u32], data: &[u8]) -> u32 {
+    let mut c = 0xffffffffu32;
+    for &b in data.iter() {
+        c = table[((c ^ (b as u32)) & 0xff) as uint] ^ (c >> 8);
+    }
+    c ^ 0xffffffff
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &d in data.iter() {
+        a = (a + d as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+fn push_u32(out: &mut ~[u8], v: u32) {
+    out.push((v >> 24) as u8);
+    out.push((v >> 16) as u8);
+    out.push((v >> 8) as u8);
+    out.push(v as u8);
+}
+
+fn push_chunk(out: &mut ~[u8], table: &[u32], kind: &str, data: &[u8]) {
+    push_u32(out, data.len() as u32);
+    let mut body = kind.as_bytes().to_owned();
+    body.push_all(data);
+    out.push_all(body);
+    push_u32(out, crc32(table, body));
+}
+
+/// Packs bits into bytes least significant bit first, as deflate does.
+struct BitWriter {
+    out: ~[u8],
+    acc: u32,
+    count: uint
+}
+
+impl BitWriter {
+    /// Appends the low `n` bits of `v`.
+    fn bits(&mut self, v: uint, n: uint) {
+        self.acc |= (v as u32) << self.count;
+        self.count += n;
+        while self.count >= 8 {
+            self.out.push(self.acc as u8);
+            self.acc >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Appends a Huffman code of `len` bits, which deflate stores most
+    /// significant bit first.
+    fn code(&mut self, code: uint, len: uint) {
+        let mut rev = 0u;
+        for i in iterator::range(0, len) {
+            rev |= ((code >> i) & 1) << (len - 1 - i);
+        }
+        self.bits(rev, len);
+    }
+
+    fn flush(&mut self) {
+        if self.count > 0 {
+            self.out.push(self.acc as u8);
+            self.acc = 0;
+            self.count = 0;
+        }
+    }
+}
+
+/// Writes a literal/length symbol with the fixed Huffman code of RFC 1951.
+fn fixed_literal(w: &mut BitWriter, sym: uint) {
+    if sym < 144 {
+        w.code(0x30 + sym, 8);
+    } else if sym < 256 {
+        w.code(0x190 + sym - 144, 9);
+    } else if sym < 280 {
+        w.code(sym - 256, 7);
+    } else {
+        w.code(0xc0 + sym - 280, 8);
+    }
+}
+
+/// Index of the last entry of `bases` that is at most `v`.
+fn base_index(bases: &[uint], v: uint) -> uint {
+    let mut i = 0;
+    while i + 1 < bases.len() && bases[i + 1] <= v {
+        i += 1;
+    }
+    i
+}
+
+fn fixed_match(w: &mut BitWriter, len: uint, dist: uint) {
+    let l = base_index(LENGTH_BASE, len);
+    fixed_literal(w, 257 + l);
+    w.bits(len - LENGTH_BASE[l], LENGTH_EXTRA[l]);
+    let d = base_index(DIST_BASE, dist);
+    w.code(d, 5);
+    w.bits(dist - DIST_BASE[d], DIST_EXTRA[d]);
+}
+
+fn hash3(data: &[u8], i: uint) -> uint {
+    (((data[i] as uint) << 10) ^ ((data[i + 1] as uint) << 5) ^ (data[i + 2] as uint)) &
+        ((1 << HASH_BITS) - 1)
+}
+
+/// Records position `i` in the hash chains. Positions are stored plus one,
+/// so that zero means none.
+fn insert_hash(data: &[u8], head: &mut [uint], prev: &mut [uint], i: uint) {
+    if i + MIN_MATCH > data.len() { return }
+    let h = hash3(data, i);
+    prev[i % WINDOW] = head[h];
+    head[h] = i + 1;
+}
+
+/// The longest earlier repeat of the bytes at `i` within the window, as a
+/// length and distance.
+fn longest_match(data: &[u8], head: &[uint], prev: &[uint], i: uint) -> (uint, uint) {
+    if i + MIN_MATCH > data.len() { return (0, 0) }
+    let max_len = if data.len() - i < MAX_MATCH { data.len() - i } else { MAX_MATCH };
+    let (mut best_len, mut best_dist) = (0u, 0u);
+    let mut cand = head[hash3(data, i)];
+    let mut chain = 0;
+    while cand > 0 && chain < MAX_CHAIN {
+        let j = cand - 1;
+        if i - j > WINDOW { break }
+        let mut l = 0;
+        while l < max_len && data[j + l] == data[i + l] {
+            l += 1;
+        }
+        if l > best_len {
+            best_len = l;
+            best_dist = i - j;
+            if l == max_len { break }
+        }
+        // an entry overwritten by a later position ends the chain
+        cand = prev[j % WINDOW];
+        if cand > 0 && cand - 1 >= j { break }
+        chain += 1;
+    }
+    (best_len, best_dist)
+}
+
+/// Wraps `data` in a zlib stream holding one deflate block with the fixed
+/// Huffman codes, using greedy LZ77 matching over hash chains.
+fn zlib_compress(data: &[u8]) -> ~[u8] {
+    let mut w = BitWriter { out: ~[0x78u8, 0x01], acc: 0, count: 0 };
+    // final block, fixed Huffman codes
+    w.bits(1, 1);
+    w.bits(1, 2);
+
+    let mut head = vec::from_elem(1 << HASH_BITS, 0u);
+    let mut prev = vec::from_elem(WINDOW, 0u);
+    let mut i = 0u;
+    while i < data.len() {
+        let (len, dist) = longest_match(data, head, prev, i);
+        if len >= MIN_MATCH {
+            fixed_match(&mut w, len, dist);
+            for k in iterator::range(i, i + len) {
+                insert_hash(data, head, prev, k);
+            }
+            i += len;
+        } else {
+            fixed_literal(&mut w, data[i] as uint);
+            insert_hash(data, head, prev, i);
+            i += 1;
+        }
+    }
+    fixed_literal(&mut w, 256);
+    w.flush();
+
+    let mut out = w.out;
+    push_u32(&mut out, adler32(data));
+    out
+}
+
+/// Appends `line` filtered with whichever of the five PNG filters gives the
+/// smallest sum of absolute differences, the usual heuristic for the filter
+/// that compresses best. `prev` is the unfiltered line above, if any.
+fn push_filtered(out: &mut ~[u8], line: &[u8], prev: Option<&[u8]>, bpp: uint) {
+    let (mut best, mut best_cost) = (~[], 0u);
+    for filter in iterator::range(0u, 5) {
+        let mut filtered = vec::with_capacity(line.len() + 1);
+        filtered.push(filter as u8);
+        let mut cost = 0u;
+        for x in iterator::range(0, line.len()) {
+            let a = if x >= bpp { line[x - bpp] } else { 0 };
+            let b = match prev { Some(p) => p[x], None => 0 };
+            let c = match prev { Some(p) if x >= bpp => p[x - bpp], _ => 0 };
+            let v = line[x] - match filter {
+                0 => 0,
+                1 => a,
+                2 => b,
+                3 => (((a as uint) + (b as uint)) / 2) as u8,
+                _ => paeth(a, b, c)
+            };
+            cost += ((v as i8) as int).abs() as uint;
+            filtered.push(v);
+        }
+        if filter == 0 || cost < best_cost {
+            best = filtered;
+            best_cost = cost;
+        }
+    }
+    out.push_all(best);
+}
+
+/// Encodes an RGB image as PNG. `samples` holds three values per pixel in
+/// row-major order, each in [0, 2^bit_depth - 1]; `bit_depth` is 8 or 16.
+/// The samples are tagged as sRGB encoded. Scanlines are filtered
+/// adaptively and deflated with the fixed Huffman codes.
+pub fn encode(width: uint, height: uint, bit_depth: uint, samples: &[u16]) -> ~[u8] {
+    assert!(bit_depth == 8 || bit_depth == 16);
+    assert!(samples.len() == width * height * 3);
+
+    let table = crc_table();
+    let bytes_per_sample = bit_depth / 8;
+    let row_len = width * 3 * bytes_per_sample;
+
+    let mut rows = vec::with_capacity(height * row_len);
+    for &s in samples.iter() {
+        if bit_depth == 16 {
+            rows.push((s >> 8) as u8);
+        }
+        rows.push(s as u8);
+    }
+
+    let mut raw = vec::with_capacity(height * (row_len + 1));
+    for y in iterator::range(0, height) {
+        let prev = if y > 0 { Some(rows.slice((y - 1) * row_len, y * row_len)) } else { None };
+        push_filtered(&mut raw, rows.slice(y * row_len, (y + 1) * row_len), prev, 3 * bytes_per_sample);
+    }
+
+    let mut out = SIGNATURE.to_owned();
+
+    let mut ihdr = ~[];
+    push_u32(&mut ihdr, width as u32);
+    push_u32(&mut ihdr, height as u32);
+    // bit depth, colour type truecolour, deflate, adaptive filtering, no interlace
+    ihdr.push_all([bit_depth as u8, 2, 0, 0, 0]);
+    push_chunk(&mut out, table, "IHDR", ihdr);
+    // rendering intent: perceptual
+    push_chunk(&mut out, table, "sRGB", [0u8]);
+    push_chunk(&mut out, table, "IDAT", zlib_compress(raw));
+    push_chunk(&mut out, table, "IEND", []);
+    out
+}
+
+static LENGTH_BASE: [uint, ..29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35,
+                                    43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+static LENGTH_EXTRA: [uint, ..29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3,
+                                     4, 4, 4, 4, 5, 5, 5, 5, 0];
+static DIST_BASE: [uint, ..30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257,
+                                  385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193,
+                                  12289, 16385, 24577];
+static DIST_EXTRA: [uint, ..30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9,
+                                   9, 10, 10, 11, 11, 12, 12, 13, 13];
+static CODE_LENGTH_ORDER: [uint, ..19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2,
+                                          14, 1, 15];
+static MAX_CODE_BITS: uint = 15;
+
+struct BitReader<'self> {
+    data: &'self [u8],
+    pos: uint,
+    bit: uint
+}
+
+impl<'self> BitReader<'self> {
+    /// The next `n` bits, least significant first.
+    fn bits(&mut self, n: uint) -> Result<uint, ~str> {
+        let mut v = 0u;
+        for i in iterator::range(0, n) {
+            if self.pos >= self.data.len() {
+                return Err(~"unexpected end of compressed data");
+            }
+            v |= (((self.data[self.pos] >> self.bit) & 1) as uint) << i;
+            self.bit += 1;
+            if self.bit == 8 {
+                self.bit = 0;
+                self.pos += 1;
+            }
+        }
+        Ok(v)
+    }
+
+    fn align(&mut self) {
+        if self.bit != 0 {
+            self.bit = 0;
+            self.pos += 1;
+        }
+    }
+}
+
+/// A canonical Huffman code: the number of codes of each length and the
+/// symbols ordered by code.
+struct Huffman {
+    counts: ~[uint],
+    symbols: ~[uint]
+}
+
+impl Huffman {
+    fn new(lengths: &[uint]) -> Huffman {
+        let mut counts = vec::from_elem(MAX_CODE_BITS + 1, 0u);
+        for &l in lengths.iter() {
+            counts[l] += 1;
+        }
+        counts[0] = 0;
+        let mut offsets = vec::from_elem(MAX_CODE_BITS + 2, 0u);
+        for l in iterator::range(1, MAX_CODE_BITS + 1) {
+            offsets[l + 1] = offsets[l] + counts[l];
+        }
+        let mut symbols = vec::from_elem(offsets[MAX_CODE_BITS + 1], 0u);
+        for (sym, &l) in lengths.iter().enumerate() {
+            if l != 0 {
+                symbols[offsets[l]] = sym;
+                offsets[l] += 1;
+            }
+        }
+        Huffman { counts: counts, symbols: symbols }
+    }
+
+    fn decode(&self, br: &mut BitReader) -> Result<uint, ~str> {
+        let (mut code, mut first, mut index) = (0u, 0u, 0u);
+        for l in iterator::range(1, MAX_CODE_BITS + 1) {
+            code |= try!(br.bits(1));
+            let count = self.counts[l];
+            if code < first + count {
+                return Ok(self.symbols[index + code - first]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(~"invalid Huffman code")
+    }
+}
+
+fn inflate_block(br: &mut BitReader, out: &mut ~[u8], lit: &Huffman, dist: &Huffman)
+    -> Result<(), ~str>
+{
+    loop {
+        let sym = try!(lit.decode(br));
+        if sym < 256 {
+            out.push(sym as u8);
+        } else if sym == 256 {
+            return Ok(());
+        } else {
+            let sym = sym - 257;
+            if sym >= 29 { return Err(~"invalid length code") }
+            let len = LENGTH_BASE[sym] + try!(br.bits(LENGTH_EXTRA[sym]));
+            let dsym = try!(dist.decode(br));
+            if dsym >= 30 { return Err(~"invalid distance code") }
+            let d = DIST_BASE[dsym] + try!(br.bits(DIST_EXTRA[dsym]));
+            if d > out.len() { return Err(~"distance too far back") }
+            let start = out.len() - d;
+            for i in iterator::range(0, len) {
+                let b = out[start + i];
+                out.push(b);
+            }
+        }
+    }
+}
+
+fn dynamic_codes(br: &mut BitReader) -> Result<(Huffman, Huffman), ~str> {
+    let nlit = try!(br.bits(5)) + 257;
+    let ndist = try!(br.bits(5)) + 1;
+    let nclen = try!(br.bits(4)) + 4;
+
+    let mut clen = [0u, ..19];
+    for i in iterator::range(0, nclen) {
+        clen[CODE_LENGTH_ORDER[i]] = try!(br.bits(3));
+    }
+    let clen_code = Huffman::new(clen);
+
+    let mut lengths = ~[];
+    while lengths.len() < nlit + ndist {
+        let sym = try!(clen_code.decode(br));
+        let (value, repeat) = match sym {
+            0..15 => (sym, 1),
+            16 => {
+                if lengths.len() == 0 { return Err(~"repeat with no previous length") }
+                (lengths[lengths.len() - 1], 3 + try!(br.bits(2)))
+            },
+            17 => (0, 3 + try!(br.bits(3))),
+            _ => (0, 11 + try!(br.bits(7)))
+        };
+        for _ in iterator::range(0, repeat) {
+            lengths.push(value);
+        }
+    }
+    if lengths.len() > nlit + ndist { return Err(~"code lengths overrun") }
+    Ok((Huffman::new(lengths.slice(0, nlit)), Huffman::new(lengths.slice(nlit, nlit + ndist))))
+}
+
+/// Decompresses a zlib stream. The Adler-32 checksum is verified.
+pub fn zlib_inflate(data: &[u8]) -> Result<~[u8], ~str> {
+    if data.len() < 6 || data[0] & 0x0f != 8 || ((data[0] as uint) * 256 + data[1] as uint) % 31 != 0 {
+        return Err(~"invalid zlib header");
+    }
+    if data[1] & 0x20 != 0 {
+        return Err(~"zlib preset dictionaries are not supported");
+    }
+
+    let mut br = BitReader { data: data, pos: 2, bit: 0 };
+    let mut out = ~[];
+    loop {
+        let last = try!(br.bits(1)) == 1;
+        match try!(br.bits(2)) {
+            0 => {
+                br.align();
+                if br.pos + 4 > data.len() { return Err(~"truncated stored block") }
+                let len = (data[br.pos] as uint) | ((data[br.pos + 1] as uint) << 8);
+                br.pos += 4;
+                if br.pos + len > data.len() { return Err(~"truncated stored block") }
+                out.push_all(data.slice(br.pos, br.pos + len));
+                br.pos += len;
+            },
+            1 => {
+                let mut lengths = vec::from_elem(288, 8u);
+                for i in iterator::range(144, 256) { lengths[i] = 9 }
+                for i in iterator::range(256, 280) { lengths[i] = 7 }
+                let lit = Huffman::new(lengths);
+                let dist = Huffman::new(vec::from_elem(30, 5u));
+                try!(inflate_block(&mut br, &mut out, &lit, &dist));
+            },
+            2 => {
+                let (lit, dist) = try!(dynamic_codes(&mut br));
+                try!(inflate_block(&mut br, &mut out, &lit, &dist));
+            },
+            _ => return Err(~"invalid block type")
+        }
+        if last { break }
+    }
+
+    br.align();
+    if br.pos + 4 > data.len() { return Err(~"missing zlib checksum") }
+    let stored = (data[br.pos] as u32) << 24 | (data[br.pos + 1] as u32) << 16 |
+                 (data[br.pos + 2] as u32) << 8 | (data[br.pos + 3] as u32);
+    if stored != adler32(out) { return Err(~"zlib checksum mismatch") }
+    Ok(out)
+}
+
+fn read_u32(data: &[u8], pos: uint) -> u32 {
+    (data[pos] as u32) << 24 | (data[pos + 1] as u32) << 16 |
+    (data[pos + 2] as u32) << 8 | (data[pos + 3] as u32)
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let (ia, ib, ic) = (a as int, b as int, c as int);
+    let p = ia + ib - ic;
+    let (pa, pb, pc) = ((p - ia).abs(), (p - ib).abs(), (p - ic).abs());
+    if pa <= pb && pa <= pc { a } else if pb <= pc { b } else { c }
+}
+
+/// Reverses the per-scanline filters in place, leaving `height` rows of
+/// `stride` bytes without their filter type bytes.
+fn unfilter(raw: &[u8], height: uint, stride: uint, bpp: uint) -> Result<~[u8], ~str> {
+    if raw.len() < height * (stride + 1) { return Err(~"image data too short") }
+    let mut out = vec::from_elem(height * stride, 0u8);
+    for y in iterator::range(0, height) {
+        let filter = raw[y * (stride + 1)];
+        let line = raw.slice(y * (stride + 1) + 1, (y + 1) * (stride + 1));
+        for x in iterator::range(0, stride) {
+            let a = if x >= bpp { out[y * stride + x - bpp] } else { 0 };
+            let b = if y > 0 { out[(y - 1) * stride + x] } else { 0 };
+            let c = if x >= bpp && y > 0 { out[(y - 1) * stride + x - bpp] } else { 0 };
+            out[y * stride + x] = line[x] + match filter {
+                0 => 0,
+                1 => a,
+                2 => b,
+                3 => (((a as uint) + (b as uint)) / 2) as u8,
+                4 => paeth(a, b, c),
+                _ => return Err(fmt!("invalid filter type %u", filter as uint))
+            };
+        }
+    }
+    Ok(out)
+}
+
+/// Decodes a non-interlaced PNG of any colour type and bit depth. Returns
+/// the width, the height and three samples per pixel in [0, 1], row-major
+/// from the top row, still in the file's (normally sRGB) encoding. Alpha is
+/// discarded.
+pub fn decode(data: &[u8]) -> Result<(uint, uint, ~[float]), ~str> {
+    if data.len() < 8 || data.slice(0, 8) != SIGNATURE.as_slice() {
+        return Err(~"not a PNG file");
+    }
+
+    let (mut width, mut height, mut depth, mut color_type) = (0u, 0u, 0u, 0u);
+    let mut palette: ~[u8] = ~[];
+    let mut compressed = ~[];
+    let mut pos = 8;
+    loop {
+        if pos + 12 > data.len() { return Err(~"truncated chunk") }
+        let len = read_u32(data, pos) as uint;
+        let kind = data.slice(pos + 4, pos + 8);
+        if pos + 12 + len > data.len() { return Err(~"truncated chunk") }
+        let body = data.slice(pos + 8, pos + 8 + len);
+        match kind {
+            [73, 72, 68, 82] => {
+                // IHDR
+                if len != 13 { return Err(~"invalid IHDR chunk") }
+                width = read_u32(body, 0) as uint;
+                height = read_u32(body, 4) as uint;
+                depth = body[8] as uint;
+                color_type = body[9] as uint;
+                if body[12] != 0 { return Err(~"interlaced PNGs are not supported") }
+            },
+            [80, 76, 84, 69] => palette = body.to_owned(), // PLTE
+            [73, 68, 65, 84] => compressed.push_all(body), // IDAT
+            [73, 69, 78, 68] => break,                     // IEND
+            _ => ()
+        }
+        pos += 12 + len;
+    }
+
+    let channels = match color_type {
+        0 => 1,
+        2 => 3,
+        3 => 1,
+        4 => 2,
+        6 => 4,
+        _ => return Err(fmt!("invalid colour type %u", color_type))
+    };
+    let valid_depth = match color_type {
+        0 => depth == 1 || depth == 2 || depth == 4 || depth == 8 || depth == 16,
+        3 => depth == 1 || depth == 2 || depth == 4 || depth == 8,
+        _ => depth == 8 || depth == 16
+    };
+    if width == 0 || height == 0 || !valid_depth {
+        return Err(fmt!("unsupported image header (%ux%u, depth %u)", width, height, depth));
+    }
+
+    let raw = try!(zlib_inflate(compressed));
+    let bits_per_pixel = channels * depth;
+    let stride = (width * bits_per_pixel + 7) / 8;
+    let bpp = if bits_per_pixel < 8 { 1 } else { bits_per_pixel / 8 };
+    let pixels = try!(unfilter(raw, height, stride, bpp));
+
+    let max = ((1u << depth) - 1) as float;
+    let sample = |y: uint, i: uint| -> uint {
+        let row = pixels.slice(y * stride, (y + 1) * stride);
+        match depth {
+            16 => (row[2 * i] as uint) << 8 | row[2 * i + 1] as uint,
+            8 => row[i] as uint,
+            _ => {
+                let bit = i * depth;
+                ((row[bit / 8] >> (8 - depth - bit % 8)) as uint) & ((1 << depth) - 1)
+            }
+        }
+    };
+
+    let mut out = vec::with_capacity(width * height * 3);
+    for y in iterator::range(0, height) {
+        for x in iterator::range(0, width) {
+            match color_type {
+                3 => {
+                    let i = sample(y, x);
+                    if 3 * i + 2 >= palette.len() { return Err(~"palette index out of range") }
+                    for c in iterator::range(0, 3) {
+                        out.push(palette[3 * i + c] as float / 255.0);
+                    }
+                },
+                0 | 4 => {
+                    let v = sample(y, x * channels) as float / max;
+                    out.push_all([v, v, v]);
+                },
+                _ => {
+                    for c in iterator::range(0, 3) {
+                        out.push(sample(y, x * channels + c) as float / max);
+                    }
+                }
+            }
+        }
+    }
+    Ok((width, height, out))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{encode, decode, zlib_compress, zlib_inflate};
+    use std::{vec, iterator};
+
+    #[test]
+    fn compressed_data_inflates_back() {
+        let data = do vec::from_fn(100000) |i| ((i / 7) % 13 + (i * i) % 5) as u8;
+        let z = zlib_compress(data);
+        assert!(z.len() < data.len() / 4);
+        assert_eq!(zlib_inflate(z).unwrap(), data);
+        assert_eq!(zlib_inflate(zlib_compress([])).unwrap(), ~[]);
+    }
+
+    #[test]
+    fn images_round_trip() {
+        let (width, height) = (64u, 48u);
+        for &depth in [8u, 16].iter() {
+            let max = (1u << depth) - 1;
+            let samples = do vec::from_fn(width * height * 3) |i| {
+                let (x, y) = ((i / 3) % width, (i / 3) / width);
+                ((x * 4 + y * (i % 3)) * max / (width * 4 + height * 2)) as u16
+            };
+            let png = encode(width, height, depth, samples);
+            assert!(png.len() < samples.len() * depth / 8);
+            let (w, h, decoded) = decode(png).unwrap();
+            assert!(w == width && h == height);
+            for i in iterator::range(0, samples.len()) {
+                assert_eq!((decoded[i] * (max as float)).round() as u16, samples[i]);
+            }
+        }
+    }
+}
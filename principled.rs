@@ -0,0 +1,227 @@
+use nalgebra::vec::*;
+use std::{float, iterator};
+use image::{RGB, lerp};
+use bsdf;
+use bsdf::{BSDF, BSDFSample, Shading};
+use microfacet;
+use microfacet::TrowbridgeReitz;
+use sampler;
+
+type Vec3f = Vec3<float>;
+
+/// Roughness below this would make the specular lobes delta distributions,
+/// which the component mixture cannot represent.
+static MIN_ROUGHNESS: float = 0.05;
+
+static DIFFUSE: uint = 0;
+static SPECULAR: uint = 1;
+static CLEARCOAT: uint = 2;
+static TRANSMISSION: uint = 3;
+
+/// Parameters of the principled material. Everything except `ior` is in
+/// [0, 1].
+#[deriving(Clone)]
+pub struct PrincipledParams {
+    metallic: float,
+    roughness: float,
+    /// Dielectric specular reflectance, where 0.5 is 4%.
+    specular: float,
+    /// Tints dielectric specular reflection towards the base colour.
+    specular_tint: float,
+    /// Retroreflective grazing sheen for cloth.
+    sheen: float,
+    sheen_tint: float,
+    /// Strength of a clear, white secondary specular layer.
+    clearcoat: float,
+    clearcoat_gloss: float,
+    transmission: float,
+    ior: float
+}
+
+impl PrincipledParams {
+    pub fn default() -> PrincipledParams {
+        PrincipledParams {
+            metallic: 0.0,
+            roughness: 0.5,
+            specular: 0.5,
+            specular_tint: 0.0,
+            sheen: 0.0,
+            sheen_tint: 0.5,
+            clearcoat: 0.0,
+            clearcoat_gloss: 1.0,
+            transmission: 0.0,
+            ior: 1.5
+        }
+    }
+}
+
+fn schlick_weight(cos: float) -> float {
+    let m = 1.0 - (if cos < 0.0 { 0.0 } else if cos > 1.0 { 1.0 } else { cos });
+    (m * m) * (m * m) * m
+}
+
+/// An uber material after Burley, "Physically-Based Shading at Disney", with
+/// the transmission extension of his 2015 follow-up. The base colour is the
+/// shading albedo, so it follows the material colour. Components are added
+/// together with weights derived from the parameters and sampled in
+/// proportion to those weights.
+pub struct Principled {
+    params: PrincipledParams,
+    specular: TrowbridgeReitz,
+    clearcoat: TrowbridgeReitz,
+    glass: bsdf::RoughDielectric
+}
+
+impl Principled {
+    pub fn new(params: PrincipledParams) -> Principled {
+        let r = if params.roughness < MIN_ROUGHNESS { MIN_ROUGHNESS } else { params.roughness };
+        let alpha_c = lerp(0.1, 0.01, params.clearcoat_gloss);
+        Principled {
+            specular: TrowbridgeReitz::from_roughness(r, r),
+            clearcoat: TrowbridgeReitz::new(alpha_c, alpha_c),
+            glass: bsdf::RoughDielectric {
+                ior: params.ior,
+                distribution: TrowbridgeReitz::from_roughness(r, r)
+            },
+            params: params
+        }
+    }
+
+    /// Weights of the diffuse and sheen, specular, clearcoat and
+    /// transmission components, which also serve as sampling probabilities
+    /// once normalised.
+    fn weights(&self) -> [float, ..4] {
+        let p = &self.params;
+        let transmission = (1.0 - p.metallic) * p.transmission;
+        [(1.0 - p.metallic) * (1.0 - p.transmission),
+         1.0 - transmission,
+         0.25 * p.clearcoat,
+         transmission]
+    }
+
+    fn probabilities(&self) -> [float, ..4] {
+        let w = self.weights();
+        let total = w[0] + w[1] + w[2] + w[3];
+        [w[0] / total, w[1] / total, w[2] / total, w[3] / total]
+    }
+
+    /// The reflection components for `wo` and `wi` in the upper hemisphere.
+    fn eval_reflection(&self, sh: &Shading, wo: &Vec3f, wi: &Vec3f, weights: &[float, ..4]) -> RGB {
+        let p = &self.params;
+        let wh = (*wo + *wi).normalized();
+        let cos_d = wi.dot(&wh);
+        let base = sh.albedo;
+        let lum = base.luminance();
+        let tint = if lum > 0.0 { base.mul_t(1.0 / lum) } else { RGB::white() };
+        let mut f = RGB::black();
+
+        if weights[DIFFUSE] > 0.0 {
+            let roughness = sh.roughness.unwrap_or(p.roughness);
+            let fd90 = 0.5 + 2.0 * roughness * cos_d * cos_d;
+            let fd = (1.0 + (fd90 - 1.0) * schlick_weight(wi.z)) *
+                     (1.0 + (fd90 - 1.0) * schlick_weight(wo.z));
+            let diffuse = base.mul_t(fd / float::consts::pi);
+            let sheen = RGB::white().lerp(&tint, p.sheen_tint).mul_t(p.sheen * schlick_weight(cos_d));
+            f = f.add_v(&diffuse.add_v(&sheen).mul_t(weights[DIFFUSE]));
+        }
+
+        if weights[SPECULAR] > 0.0 {
+            let f0 = RGB::white().lerp(&tint, p.specular_tint).mul_t(0.08 * p.specular)
+                                 .lerp(&base, p.metallic);
+            let fr = f0.lerp(&RGB::white(), schlick_weight(cos_d));
+            let specular = self.specular.at(sh.roughness);
+            let dg = specular.d(&wh) * specular.g(wo, wi) / (4.0 * wo.z * wi.z);
+            f = f.add_v(&fr.mul_t(dg * weights[SPECULAR]));
+        }
+
+        if weights[CLEARCOAT] > 0.0 {
+            let fr = lerp(0.04, 1.0, schlick_weight(cos_d));
+            let dg = self.clearcoat.d(&wh) * self.clearcoat.g(wo, wi) / (4.0 * wo.z * wi.z);
+            f = f.add_v(&RGB::white().mul_t(fr * dg * weights[CLEARCOAT]));
+        }
+        f
+    }
+}
+
+impl BSDF for Principled {
+    fn sample(&self, sh: &Shading, wo: &Vec3f, u: (float, float), uc: float)
+        -> Option<BSDFSample>
+    {
+        if wo.z == 0.0 { return None }
+        let probs = self.probabilities();
+        let mut acc = 0.0;
+        let mut chosen = TRANSMISSION;
+        for i in iterator::range(0u, 4) {
+            if uc < acc + probs[i] {
+                chosen = i;
+                break;
+            }
+            acc += probs[i];
+        }
+        if probs[chosen] == 0.0 { return None }
+
+        let wo_up = bsdf::to_upper(wo);
+        let wi = if chosen == DIFFUSE {
+            let (u1, u2) = u;
+            let (wi, pdf) = sh.hemisphere.sample(u1, u2);
+            if pdf == 0.0 { return None }
+            wi
+        } else if chosen == SPECULAR || chosen == CLEARCOAT {
+            let distribution = if chosen == SPECULAR { self.specular.at(sh.roughness) }
+                               else { self.clearcoat.clone() };
+            let wm = distribution.sample_visible(&wo_up, u);
+            let wi = microfacet::reflect(&wo_up, &wm);
+            if wi.z <= 0.0 { return None }
+            wi
+        } else {
+            let uc = (uc - acc) / probs[chosen];
+            let uc = if uc < sampler::ONE_MINUS_EPSILON { uc } else { sampler::ONE_MINUS_EPSILON };
+            match self.glass.sample(sh, wo, u, uc) {
+                Some(s) => s.wi,
+                None => return None
+            }
+        };
+        let wi = if chosen != TRANSMISSION && wo.z < 0.0 { Vec3::new(wi.x, wi.y, -wi.z) } else { wi };
+
+        let pdf = self.pdf(sh, wo, &wi);
+        if pdf == 0.0 { return None }
+        Some(BSDFSample { wi: wi, f: self.eval(sh, wo, &wi), pdf: pdf, delta: false })
+    }
+
+    fn eval(&self, sh: &Shading, wo: &Vec3f, wi: &Vec3f) -> RGB {
+        let weights = self.weights();
+        let mut f = if weights[TRANSMISSION] > 0.0 {
+            self.glass.eval(sh, wo, wi).mul_t(weights[TRANSMISSION])
+        } else {
+            RGB::black()
+        };
+        if bsdf::same_hemisphere(wo, wi) {
+            f = f.add_v(&self.eval_reflection(sh, &bsdf::to_upper(wo), &bsdf::to_upper(wi), &weights));
+        }
+        f
+    }
+
+    fn pdf(&self, sh: &Shading, wo: &Vec3f, wi: &Vec3f) -> float {
+        let probs = self.probabilities();
+        let mut pdf = if probs[TRANSMISSION] > 0.0 {
+            probs[TRANSMISSION] * self.glass.pdf(sh, wo, wi)
+        } else {
+            0.0
+        };
+        if !bsdf::same_hemisphere(wo, wi) { return pdf }
+
+        let (wo, wi) = (bsdf::to_upper(wo), bsdf::to_upper(wi));
+        let wh = (wo + wi).normalized();
+        let cos_h = 4.0 * wo.dot(&wh).abs();
+        pdf += probs[DIFFUSE] * sh.hemisphere.pdf(wi.z);
+        pdf += probs[SPECULAR] * self.specular.at(sh.roughness).d_visible(&wo, &wh) / cos_h;
+        pdf += probs[CLEARCOAT] * self.clearcoat.d_visible(&wo, &wh) / cos_h;
+        pdf
+    }
+
+    fn is_delta(&self) -> bool { false }
+
+    fn clone_bsdf(&self) -> ~BSDF:Send+Freeze {
+        ~Principled::new(self.params.clone()) as ~BSDF:Send+Freeze
+    }
+}
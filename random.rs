@@ -1,15 +1,195 @@
-use std::rand;
-use std::rand::RngUtil;
 use nalgebra::vec::*;
+use std::float;
 
-pub fn random_real() -> float {
-    rand::task_rng().gen_uint_range(0,1000) as float / 999.0
+/// PCG32 (O'Neill, "PCG: A Family of Simple Fast Space-Efficient
+/// Statistically Good Algorithms for Random Number Generation"): 64 bits of
+/// state, one of 2^63 streams selected by `inc`. Every pixel sample gets its
+/// own stream derived from the render seed, so the image does not depend on
+/// which task traced which sample.
+pub struct Rng {
+    state: u64,
+    inc: u64
 }
 
-pub fn random_vec() -> Vec3<float> {
-    let th = random_real() * 2.0 * 3.14;
-    let z = -1.0 + 2.0 * random_real();
+static PCG_MULTIPLIER: u64 = 6364136223846793005;
+
+/// SplitMix64 finaliser, used to turn structured seeds into well mixed state.
+pub fn mix(x: u64) -> u64 {
+    let mut z = x + 0x9e3779b97f4a7c15;
+    z = (z ^ (z >> 30)) * 0xbf58476d1ce4e5b9;
+    z = (z ^ (z >> 27)) * 0x94d049bb133111eb;
+    z ^ (z >> 31)
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng::with_stream(seed, 0)
+    }
+
+    pub fn with_stream(seed: u64, stream: u64) -> Rng {
+        let mut rng = Rng { state: 0, inc: (stream << 1) | 1 };
+        rng.next_u32();
+        rng.state += seed;
+        rng.next_u32();
+        rng
+    }
+
+    /// The stream for sample number `sample` of pixel `pixel`.
+    pub fn for_sample(seed: u64, pixel: uint, sample: uint) -> Rng {
+        Rng::with_stream(mix(mix(seed) ^ (sample as u64)), mix(pixel as u64))
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.state = old * PCG_MULTIPLIER + self.inc;
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        let rot = (old >> 59) as u32;
+        (xorshifted >> rot) | (xorshifted << ((32 - rot) & 31))
+    }
+
+    /// Uniform in [0, 1), using all 53 bits of the mantissa.
+    pub fn random_real(&mut self) -> float {
+        let hi = (self.next_u32() >> 5) as u64;
+        let lo = (self.next_u32() >> 6) as u64;
+        ((hi << 26) | lo) as float * (1.0 / 9007199254740992.0)
+    }
+}
+
+/// Maps a point of the unit square uniformly onto the unit sphere.
+pub fn uniform_sphere(u: float, v: float) -> Vec3<float> {
+    let th = u * 2.0 * float::consts::pi;
+    let z = -1.0 + 2.0 * v;
     let t = (1.0 - z * z).sqrt();
 
     Vec3::new(t * th.cos(), t * th.sin(), z)
 }
+
+pub enum HemisphereSampling {
+    CosineWeighted,
+    UniformHemisphere
+}
+
+impl HemisphereSampling {
+    pub fn from_str(s: &str) -> Option<HemisphereSampling> {
+        match s {
+            "cosine" => Some(CosineWeighted),
+            "uniform" => Some(UniformHemisphere),
+            _ => None
+        }
+    }
+
+    /// A direction in the hemisphere around +z and its solid angle density.
+    pub fn sample(&self, u: float, v: float) -> (Vec3<float>, float) {
+        match *self {
+            CosineWeighted => {
+                let d = cosine_hemisphere(u, v);
+                (d, d.z / float::consts::pi)
+            },
+            UniformHemisphere => (uniform_hemisphere(u, v), 0.5 / float::consts::pi)
+        }
+    }
+
+    /// Density of `sample` choosing a direction with the given cosine to +z.
+    pub fn pdf(&self, cos_theta: float) -> float {
+        if cos_theta <= 0.0 { return 0.0 }
+        match *self {
+            CosineWeighted => cos_theta / float::consts::pi,
+            UniformHemisphere => 0.5 / float::consts::pi
+        }
+    }
+}
+
+/// Maps a point of the unit square uniformly onto the hemisphere around +z.
+pub fn uniform_hemisphere(u: float, v: float) -> Vec3<float> {
+    let d = uniform_sphere(u, v);
+    Vec3::new(d.x, d.y, d.z.abs())
+}
+
+/// Maps a point of the unit square onto the hemisphere around +z with
+/// density proportional to the cosine to +z, by projecting a uniformly
+/// sampled disk point (Malley's method).
+pub fn cosine_hemisphere(u: float, v: float) -> Vec3<float> {
+    let r = u.sqrt();
+    let phi = 2.0 * float::consts::pi * v;
+    let (x, y) = (r * phi.cos(), r * phi.sin());
+    let z = 1.0 - x * x - y * y;
+    Vec3::new(x, y, if z > 0.0 { z.sqrt() } else { 0.0 })
+}
+
+/// Two unit vectors completing `n` to an orthonormal basis (Duff et al.,
+/// "Building an Orthonormal Basis, Revisited").
+pub fn coordinate_system(n: &Vec3<float>) -> (Vec3<float>, Vec3<float>) {
+    let sign = if n.z >= 0.0 { 1.0 } else { -1.0 };
+    let a = -1.0 / (sign + n.z);
+    let b = n.x * n.y * a;
+    (Vec3::new(1.0 + sign * n.x * n.x * a, sign * b, -sign * n.x),
+     Vec3::new(b, sign + n.y * n.y * a, -n.y))
+}
+
+/// Expresses `local`, given relative to +z, in the frame around `n`.
+pub fn local_to_world(local: &Vec3<float>, n: &Vec3<float>) -> Vec3<float> {
+    let (t, b) = coordinate_system(n);
+    t * local.x + b * local.y + *n * local.z
+}
+
+#[cfg(test)]
+mod test {
+    use super::Rng;
+    use std::iterator;
+
+    static DRAWS: uint = 1000000;
+
+    #[test]
+    fn random_real_in_unit_interval() {
+        let mut rng = Rng::new(1);
+        for _ in iterator::range(0, DRAWS) {
+            let x = rng.random_real();
+            assert!(x >= 0.0 && x < 1.0, fmt!("%? outside [0, 1)", x));
+        }
+    }
+
+    #[test]
+    fn random_real_mean_and_variance() {
+        let mut rng = Rng::new(2);
+        let (mut sum, mut sum_sq) = (0.0, 0.0);
+        for _ in iterator::range(0, DRAWS) {
+            let x = rng.random_real();
+            sum += x;
+            sum_sq += x * x;
+        }
+        let n = DRAWS as float;
+        let mean = sum / n;
+        let variance = sum_sq / n - mean * mean;
+        // the standard error of the mean is about 3e-4
+        assert!((mean - 0.5).abs() < 2e-3, fmt!("mean %?", mean));
+        assert!((variance - 1.0 / 12.0).abs() < 1e-3, fmt!("variance %?", variance));
+    }
+
+    #[test]
+    fn random_real_chi_square() {
+        let mut rng = Rng::new(3);
+        let mut bins = [0u, ..100];
+        for _ in iterator::range(0, DRAWS) {
+            bins[(rng.random_real() * 100.0) as uint] += 1;
+        }
+        let expected = (DRAWS as float) / 100.0;
+        let chi2 = bins.iter().fold(0.0, |acc, &b| {
+            let d = (b as float) - expected;
+            acc + d * d / expected
+        });
+        // 99 degrees of freedom: exceeded with probability 0.001
+        assert!(chi2 < 148.2, fmt!("chi-square %?", chi2));
+    }
+
+    #[test]
+    fn random_real_uses_53_bits() {
+        let mut rng = Rng::new(4);
+        let (mut odd, mut below_32_bits) = (false, false);
+        for _ in iterator::range(0, 1000) {
+            let bits = (rng.random_real() * 9007199254740992.0) as u64;
+            if bits & 1 != 0 { odd = true }
+            if bits & 0x1fffff != 0 { below_32_bits = true }
+        }
+        assert!(odd && below_32_bits);
+    }
+}
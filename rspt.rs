@@ -1,14 +1,38 @@
 extern mod nalgebra;
 extern mod extra;
 
+// must come first so the other modules see its macros
+#[macro_escape]
+mod macros;
+
 pub mod image;
 pub mod main;
 pub mod scene;
 pub mod camera;
 pub mod random;
+#[cfg(not(nosdl))]
+pub mod sdlui;
+#[cfg(nosdl)]
+#[path = "nosdl.rs"]
 pub mod sdlui;
 pub mod obj;
 pub mod aabb;
+pub mod scenefile;
+pub mod bvh;
+pub mod light;
+pub mod png;
+pub mod hdr;
+pub mod tonemap;
+pub mod sampler;
+pub mod bsdf;
+pub mod microfacet;
+pub mod principled;
+pub mod texture;
+pub mod noise;
+pub mod cli;
+pub mod transform;
+pub mod fperror;
+pub mod mesh;
 
 #[start]
 fn start(argc: int, argv: **u8, crate_map: *u8) -> int {
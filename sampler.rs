@@ -0,0 +1,313 @@
+use random;
+
+/// Source of the sample values for one pixel sample. Each call consumes the
+/// next dimension; a path uses dimensions in the same order every time, so
+/// samplers that distribute dimensions well across sample indices reduce
+/// noise compared to independent random numbers.
+pub trait Sampler {
+    /// Prepares the sampler for sample number `index` of `pixel`.
+    fn start_sample(&mut self, pixel: uint, index: uint);
+    /// A value in [0, 1).
+    fn next_1d(&mut self) -> float;
+    /// A point in [0, 1)^2.
+    fn next_2d(&mut self) -> (float, float);
+}
+
+pub enum SamplerKind {
+    IndependentSampler,
+    StratifiedSampler,
+    HaltonSampler,
+    SobolSampler
+}
+
+impl SamplerKind {
+    pub fn from_str(s: &str) -> Option<SamplerKind> {
+        match s {
+            "independent" => Some(IndependentSampler),
+            "stratified" => Some(StratifiedSampler),
+            "halton" => Some(HaltonSampler),
+            "sobol" => Some(SobolSampler),
+            _ => None
+        }
+    }
+}
+
+/// `spp` is the total number of samples per pixel, which the stratified
+/// sampler needs to know in advance.
+pub fn new(kind: SamplerKind, seed: u64, spp: uint) -> ~Sampler {
+    match kind {
+        IndependentSampler => ~Independent::new(seed) as ~Sampler,
+        StratifiedSampler => ~Stratified::new(seed, spp) as ~Sampler,
+        HaltonSampler => ~Halton::new(seed) as ~Sampler,
+        SobolSampler => ~Sobol::new(seed) as ~Sampler
+    }
+}
+
+pub static ONE_MINUS_EPSILON: float = 0.99999999999999989;
+
+fn to_unit(x: u32) -> float {
+    let f = (x as float) / 4294967296.0;
+    if f < ONE_MINUS_EPSILON { f } else { ONE_MINUS_EPSILON }
+}
+
+/// A well mixed 32-bit hash of a few values.
+fn hash(a: u64, b: u64, c: u64) -> u32 {
+    (random::mix(random::mix(random::mix(a) ^ b) ^ c) >> 32) as u32
+}
+
+pub struct Independent {
+    seed: u64,
+    rng: random::Rng
+}
+
+impl Independent {
+    pub fn new(seed: u64) -> Independent {
+        Independent { seed: seed, rng: random::Rng::new(seed) }
+    }
+}
+
+impl Sampler for Independent {
+    fn start_sample(&mut self, pixel: uint, index: uint) {
+        self.rng = random::Rng::for_sample(self.seed, pixel, index);
+    }
+
+    fn next_1d(&mut self) -> float {
+        self.rng.random_real()
+    }
+
+    fn next_2d(&mut self) -> (float, float) {
+        let u = self.rng.random_real();
+        (u, self.rng.random_real())
+    }
+}
+
+/// Kensler's hash-based permutation of [0, l), from "Correlated Multi-Jittered
+/// Sampling".
+fn permute(i: u32, l: u32, p: u32) -> u32 {
+    let mut w = l - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    let mut i = i;
+    loop {
+        i ^= p;
+        i *= 0xe170893d;
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i *= 0x0929eb3f;
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i *= 1 | p >> 27;
+        i *= 0x6935fa69;
+        i ^= (i & w) >> 11;
+        i *= 0x74dcb303;
+        i ^= (i & w) >> 2;
+        i *= 0x9e501cc3;
+        i ^= (i & w) >> 2;
+        i *= 0xc860a3df;
+        i &= w;
+        i ^= i >> 5;
+        if i < l { break }
+    }
+    (i + p) % l
+}
+
+/// Jittered strata for 1D values and correlated multi-jittered patterns for
+/// 2D values, each decorrelated across pixels and dimensions.
+pub struct Stratified {
+    seed: u64,
+    spp: u32,
+    pixel: uint,
+    index: u32,
+    dim: u64
+}
+
+impl Stratified {
+    pub fn new(seed: u64, spp: uint) -> Stratified {
+        assert!(spp > 0);
+        Stratified { seed: seed, spp: spp as u32, pixel: 0, index: 0, dim: 0 }
+    }
+
+    /// Pattern seed for the current dimension; samples past `spp` start a
+    /// fresh pattern.
+    fn pattern(&mut self) -> u32 {
+        let p = hash(self.seed, self.pixel as u64,
+                     (self.dim << 32) | ((self.index / self.spp) as u64));
+        self.dim += 1;
+        p
+    }
+}
+
+impl Sampler for Stratified {
+    fn start_sample(&mut self, pixel: uint, index: uint) {
+        self.pixel = pixel;
+        self.index = index as u32;
+        self.dim = 0;
+    }
+
+    fn next_1d(&mut self) -> float {
+        let p = self.pattern();
+        let n = self.spp;
+        let s = permute(self.index % n, n, p * 0x68bc21eb);
+        let j = to_unit(hash(p as u64, s as u64, 1));
+        ((s as float) + j) / (n as float)
+    }
+
+    fn next_2d(&mut self) -> (float, float) {
+        let p = self.pattern();
+        let n = self.spp;
+        let m = (n as float).sqrt() as u32;
+        let rows = (n + m - 1) / m;
+        let s = permute(self.index % n, n, p * 0x51633e2d);
+        let sx = permute(s % m, m, p * 0x68bc21eb);
+        let sy = permute(s / m, rows, p * 0x02e5be93);
+        let jx = to_unit(hash(p as u64, s as u64, 2));
+        let jy = to_unit(hash(p as u64, s as u64, 3));
+        let x = ((sx as float) + ((sy as float) + jx) / (rows as float)) / (m as float);
+        let y = ((s as float) + jy) / (n as float);
+        (if x < ONE_MINUS_EPSILON { x } else { ONE_MINUS_EPSILON }, y)
+    }
+}
+
+static PRIMES: [u64, ..32] = [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53,
+                              59, 61, 67, 71, 73, 79, 83, 89, 97, 101, 103, 107, 109, 113,
+                              127, 131];
+
+fn radical_inverse(base: u64, i: u64) -> float {
+    let inv_base = 1.0 / (base as float);
+    let mut inv = inv_base;
+    let mut i = i;
+    let mut r = 0.0;
+    while i > 0 {
+        r += ((i % base) as float) * inv;
+        i /= base;
+        inv *= inv_base;
+    }
+    r
+}
+
+/// The Halton sequence over sample indices, with a random toroidal shift
+/// per pixel and dimension. Dimensions past the prime table would have to
+/// reuse a base, which correlates them, so they get independent random
+/// numbers instead.
+pub struct Halton {
+    seed: u64,
+    pixel: uint,
+    index: u64,
+    dim: uint,
+    rng: random::Rng
+}
+
+impl Halton {
+    pub fn new(seed: u64) -> Halton {
+        Halton { seed: seed, pixel: 0, index: 0, dim: 0, rng: random::Rng::new(seed) }
+    }
+}
+
+impl Sampler for Halton {
+    fn start_sample(&mut self, pixel: uint, index: uint) {
+        self.pixel = pixel;
+        self.index = index as u64;
+        self.dim = 0;
+        self.rng = random::Rng::for_sample(self.seed, pixel, index);
+    }
+
+    fn next_1d(&mut self) -> float {
+        if self.dim >= PRIMES.len() {
+            self.dim += 1;
+            return self.rng.random_real();
+        }
+        let base = PRIMES[self.dim];
+        let shift = to_unit(hash(self.seed, self.pixel as u64, self.dim as u64));
+        self.dim += 1;
+        let v = radical_inverse(base, self.index) + shift;
+        let v = if v >= 1.0 { v - 1.0 } else { v };
+        if v < ONE_MINUS_EPSILON { v } else { ONE_MINUS_EPSILON }
+    }
+
+    fn next_2d(&mut self) -> (float, float) {
+        let u = self.next_1d();
+        (u, self.next_1d())
+    }
+}
+
+fn reverse_bits(x: u32) -> u32 {
+    let mut x = x;
+    x = (x << 16) | (x >> 16);
+    x = ((x & 0x00ff00ff) << 8) | ((x & 0xff00ff00) >> 8);
+    x = ((x & 0x0f0f0f0f) << 4) | ((x & 0xf0f0f0f0) >> 4);
+    x = ((x & 0x33333333) << 2) | ((x & 0xcccccccc) >> 2);
+    x = ((x & 0x55555555) << 1) | ((x & 0xaaaaaaaa) >> 1);
+    x
+}
+
+/// Owen scrambling via the hash of Laine and Karras as improved by Burley,
+/// "Practical Hash-based Owen Scrambling".
+fn owen_scramble(x: u32, seed: u32) -> u32 {
+    let mut x = reverse_bits(x);
+    x += seed;
+    x ^= x * 0x6c50b47c;
+    x ^= x * 0xb82f1e52;
+    x ^= x * 0xc7afe638;
+    x ^= x * 0x8d22f6e6;
+    reverse_bits(x)
+}
+
+/// The first two dimensions of the Sobol sequence.
+fn sobol_2d(i: u32) -> (u32, u32) {
+    let x = reverse_bits(i);
+    let mut y = 0u32;
+    let mut v = 1u32 << 31;
+    let mut i = i;
+    while i != 0 {
+        if i & 1 != 0 { y ^= v }
+        i >>= 1;
+        v ^= v >> 1;
+    }
+    (x, y)
+}
+
+/// Owen-scrambled Sobol points. Each pair of dimensions uses the first two
+/// Sobol dimensions with an independently shuffled sample index ("padding"),
+/// which avoids the correlation problems of high Sobol dimensions.
+pub struct Sobol {
+    seed: u64,
+    pixel: uint,
+    index: u32,
+    dim: u64
+}
+
+impl Sobol {
+    pub fn new(seed: u64) -> Sobol {
+        Sobol { seed: seed, pixel: 0, index: 0, dim: 0 }
+    }
+
+    fn point(&mut self) -> (float, float) {
+        let h = hash(self.seed, self.pixel as u64, self.dim);
+        self.dim += 1;
+        let i = owen_scramble(self.index, h);
+        let (x, y) = sobol_2d(i);
+        (to_unit(owen_scramble(x, h * 0x9e3779b9)),
+         to_unit(owen_scramble(y, h * 0x85ebca6b)))
+    }
+}
+
+impl Sampler for Sobol {
+    fn start_sample(&mut self, pixel: uint, index: uint) {
+        self.pixel = pixel;
+        self.index = index as u32;
+        self.dim = 0;
+    }
+
+    fn next_1d(&mut self) -> float {
+        let (x, _) = self.point();
+        x
+    }
+
+    fn next_2d(&mut self) -> (float, float) {
+        self.point()
+    }
+}
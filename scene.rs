@@ -1,93 +1,257 @@
 use nalgebra::vec::*;
 use nalgebra::mat::*;
-use nalgebra::adaptors::transform::*;
-use nalgebra::adaptors::rotmat::*;
-use Ts = nalgebra::traits::transformation::Transform;
 use image;
-use random;
 use aabb;
+use bsdf;
+use bsdf::BSDF;
+use sampler;
+use principled;
+use texture;
+use transform::Affine;
+use std::float;
+use std::iterator::range;
+use random;
+use fperror;
+use mesh;
+use extra::arc::Arc;
 
 type Vec3f = Vec3<float>;
 type Mat4f = Mat4<float>;
-pub type Transform3d = Transform<Vec3f, Rotmat<Mat3<float>>>;
+pub type Transform3d = Affine;
+
+/// Fraction of a shadow ray's length left untested at its far end, so
+/// that it does not hit the surface it was aimed at.
+static SHADOW_EPSILON: float = 1e-4;
 
+/// The points `pos + dir * t` for `t_min < t < t_max`.
 pub struct Ray {
     pos: Vec3f,
-    dir: Vec3f
+    dir: Vec3f,
+    t_min: float,
+    t_max: float
+}
+
+impl Ray {
+    /// An unbounded ray starting at `pos`.
+    pub fn new(pos: Vec3f, dir: Vec3f) -> Ray {
+        Ray { pos: pos, dir: dir, t_min: 0.0, t_max: float::infinity }
+    }
+
+    /// The same ray ending at `t_max`.
+    pub fn clipped(&self, t_max: float) -> Ray {
+        Ray { pos: self.pos, dir: self.dir, t_min: self.t_min, t_max: t_max }
+    }
+
+    pub fn contains(&self, t: float) -> bool {
+        t > self.t_min && t < self.t_max
+    }
+}
+
+/// Where a ray first meets an object.
+pub struct Hit {
+    distance: float,
+    /// The triangle hit, for meshes.
+    prim: uint
 }
 
 pub struct LinearScene {
     objs: ~[Object]
 }
 
+/// The closest surface point hit by a ray, in world space.
 pub struct Intersection<'self> {
     distance: float,
-    object: &'self Object
+    object: &'self Object,
+    position: Vec3f,
+    /// Bound on the rounding error in each component of `position`.
+    p_error: Vec3f,
+    /// Unit normal of the surface itself, pointing out of the object.
+    geometric_normal: Vec3f,
+    /// Unit normal used for shading, on the same side as `geometric_normal`.
+    shading_normal: Vec3f,
+    uv: (float, float),
+    /// Partial derivatives of `position` with respect to `uv`.
+    dpdu: Vec3f,
+    dpdv: Vec3f
+}
+
+impl<'self> Intersection<'self> {
+    pub fn tex_point(&self) -> texture::TexPoint {
+        texture::TexPoint { p: self.object.inv_transform.transform(&self.position), uv: self.uv }
+    }
+
+    /// A ray leaving the surface in direction `dir`, starting just far
+    /// enough off it not to hit it again because of rounding error.
+    pub fn spawn_ray(&self, dir: &Vec3f) -> Ray {
+        Ray::new(fperror::offset_ray_origin(&self.position, &self.p_error, &self.geometric_normal, dir),
+                 *dir)
+    }
+
+    /// A shadow ray from the surface to `target`, spanning t in (0, 1)
+    /// short of the target itself.
+    pub fn spawn_ray_to(&self, target: &Vec3f) -> Ray {
+        let pos = fperror::offset_ray_origin(&self.position, &self.p_error, &self.geometric_normal,
+                                             &(*target - self.position));
+        Ray { pos: pos, dir: *target - pos, t_min: 0.0, t_max: 1.0 - SHADOW_EPSILON }
+    }
 }
 
 pub trait Scene {
     fn intersect<'a>(&'a self, ray: &Ray) -> Option<Intersection<'a>>;
+    fn objects<'a>(&'a self) -> &'a [Object];
 }
 
 impl Scene for LinearScene {
     fn intersect<'a>(&'a self, ray: &Ray) -> Option<Intersection<'a>> {
         let mut closest = None;
+        let mut ray = *ray;
         for obj in self.objs.iter() {
-            let intr = obj.intersect(ray);
-            match (intr, closest) {
-                (Some(i), None) if i.distance > 0.0 => {
-                    closest = Some(i)
-                }
-                (Some(i), Some(c)) if i.distance > 0.0 && i.distance < c.distance => {
-                    closest = Some(i)
+            match obj.hit(&ray) {
+                Some(hit) => {
+                    ray = ray.clipped(hit.distance);
+                    closest = Some((obj, hit));
                 }
-                _ => ()
+                None => ()
             }
         }
-        closest
+        closest.map(|&(obj, hit)| obj.intersection(&ray, &hit))
     }
-}
 
-#[deriving(Eq)]
-pub enum ReflectanceFunction {
-    Diffuse,
-    Specular
+    fn objects<'a>(&'a self) -> &'a [Object] {
+        self.objs.as_slice()
+    }
 }
 
-#[deriving(Encodable)]
-pub struct ReflectanceDistribution {
-    diffuse: float,
-    specular: float
+/// One component of a material's scattering, chosen with probability
+/// `weight` when sampling.
+pub struct Lobe {
+    weight: float,
+    bsdf: ~BSDF:Send+Freeze
 }
 
-impl ReflectanceDistribution {
-    pub fn sample(&self) -> ReflectanceFunction {
-        let r = random::random_real();
-        if r <= self.diffuse { return Diffuse }
-        if r <= self.diffuse + self.specular { return Specular }
-        fail!("non-1.0 reflectance distribution, diffuse %f, specular %f",
-              self.diffuse, self.specular);
+impl Clone for Lobe {
+    fn clone(&self) -> Lobe {
+        Lobe { weight: self.weight, bsdf: self.bsdf.clone_bsdf() }
     }
 }
 
-#[deriving(Encodable)]
+#[deriving(Clone)]
 pub struct Material {
-    rfd: ReflectanceDistribution,
-    color: image::RGB,
-    emission: image::RGB
+    lobes: ~[Lobe],
+    color: texture::Texture,
+    emission: texture::Texture,
+    /// Replaces the roughness of the microfacet lobes where present.
+    roughness: Option<texture::Texture>
 }
 
 impl Material {
-    pub fn diffuse(color: image::RGB, emission: image::RGB) -> Material {
-        Material {
-            rfd: ReflectanceDistribution { diffuse: 1.0, specular: 0.0 },
-            color: color,
-            emission: emission
+    /// Lobe weights are normalised to sum to one.
+    pub fn new(lobes: ~[Lobe], color: texture::Texture, emission: texture::Texture) -> Material {
+        let total = lobes.iter().fold(0.0, |acc, l| acc + l.weight);
+        let mut lobes = lobes;
+        if total > 0.0 {
+            for l in lobes.mut_iter() {
+                l.weight /= total;
+            }
+        }
+        Material { lobes: lobes, color: color, emission: emission, roughness: None }
+    }
+
+    pub fn diffuse(color: texture::Texture, emission: texture::Texture) -> Material {
+        Material::new(~[Lobe { weight: 1.0, bsdf: ~bsdf::Lambertian as ~BSDF:Send+Freeze }],
+                      color, emission)
+    }
+
+    /// A mix of Lambertian, mirror and smooth dielectric lobes.
+    pub fn from_weights(diffuse: float, specular: float, refractive: float, ior: float,
+                        color: texture::Texture, emission: texture::Texture) -> Material {
+        let mut lobes = ~[];
+        if diffuse > 0.0 {
+            lobes.push(Lobe { weight: diffuse, bsdf: ~bsdf::Lambertian as ~BSDF:Send+Freeze });
+        }
+        if specular > 0.0 {
+            lobes.push(Lobe { weight: specular, bsdf: ~bsdf::SpecularReflection as ~BSDF:Send+Freeze });
+        }
+        if refractive > 0.0 {
+            lobes.push(Lobe { weight: refractive,
+                              bsdf: ~bsdf::SpecularDielectric { ior: ior } as ~BSDF:Send+Freeze });
+        }
+        Material::new(lobes, color, emission)
+    }
+
+    /// A single principled lobe with `color` as its base colour.
+    pub fn principled(params: principled::PrincipledParams, color: texture::Texture,
+                      emission: texture::Texture) -> Material {
+        Material::new(~[Lobe { weight: 1.0,
+                               bsdf: ~principled::Principled::new(params) as ~BSDF:Send+Freeze }],
+                      color, emission)
+    }
+
+    /// True if some lobe can be evaluated for arbitrary directions, which is
+    /// what direct light sampling needs.
+    pub fn has_non_delta(&self) -> bool {
+        self.lobes.iter().any(|l| !l.bsdf.is_delta())
+    }
+
+    /// Picks a lobe with `uc` and samples it with `u`; the position of `uc`
+    /// within the chosen lobe's interval is passed on to the lobe. For
+    /// non-delta lobes the returned value and density are those of the whole
+    /// mixture.
+    pub fn sample(&self, sh: &bsdf::Shading, wo: &Vec3f, u: (float, float), uc: float)
+        -> Option<bsdf::BSDFSample>
+    {
+        let mut acc = 0.0;
+        let mut chosen = None;
+        for l in self.lobes.iter() {
+            if uc < acc + l.weight {
+                chosen = Some(l);
+                break;
+            }
+            acc += l.weight;
         }
+        let lobe = match chosen {
+            Some(l) => l,
+            None => match self.lobes.last_opt() {
+                Some(l) => {
+                    acc -= l.weight;
+                    l
+                }
+                None => return None
+            }
+        };
+        let uc = if lobe.weight > 0.0 { (uc - acc) / lobe.weight } else { 0.0 };
+        let uc = if uc < 0.0 { 0.0 }
+                 else if uc > sampler::ONE_MINUS_EPSILON { sampler::ONE_MINUS_EPSILON }
+                 else { uc };
+
+        match lobe.bsdf.sample(sh, wo, u, uc) {
+            Some(s) if s.delta => {
+                Some(bsdf::BSDFSample { wi: s.wi, f: s.f.mul_t(lobe.weight),
+                                        pdf: s.pdf * lobe.weight, delta: true })
+            },
+            Some(s) => {
+                let pdf = self.pdf(sh, wo, &s.wi);
+                if pdf == 0.0 { return None }
+                Some(bsdf::BSDFSample { wi: s.wi, f: self.eval(sh, wo, &s.wi), pdf: pdf,
+                                        delta: false })
+            },
+            None => None
+        }
+    }
+
+    pub fn eval(&self, sh: &bsdf::Shading, wo: &Vec3f, wi: &Vec3f) -> image::RGB {
+        self.lobes.iter().fold(image::RGB::black(), |acc, l| {
+            acc.add_v(&l.bsdf.eval(sh, wo, wi).mul_t(l.weight))
+        })
+    }
+
+    pub fn pdf(&self, sh: &bsdf::Shading, wo: &Vec3f, wi: &Vec3f) -> float {
+        self.lobes.iter().fold(0.0, |acc, l| acc + l.weight * l.bsdf.pdf(sh, wo, wi))
     }
 }
 
 pub struct Object {
+    transform: Transform3d,
     inv_transform: Transform3d,
     shape: Shape,
     material: Material
@@ -96,30 +260,114 @@ pub struct Object {
 impl Object {
     pub fn new(transform: Transform3d, shape: Shape, material: Material) -> Object {
         Object {
-            inv_transform: transform.inv_transformation(),
+            inv_transform: transform.inverse(),
+            transform: transform,
             shape: shape,
             material: material
         }
     }
 }
 
-fn minf(a: float, b: float) -> float { if a < b { a } else { b } }
-fn maxf(a: float, b: float) -> float { if a < b { b } else { a } }
+pub fn minf(a: float, b: float) -> float { if a < b { a } else { b } }
+pub fn maxf(a: float, b: float) -> float { if a < b { b } else { a } }
 
+/// The ray in object space. The direction is left unnormalised so that
+/// distances along it are the same as along the world space ray.
 fn transform_ray(ray: &Ray, inv_transform: &Transform3d) -> Ray {
-    let tpos = ray.pos + ray.dir;
-    let apos = inv_transform.transform(&ray.pos);
     Ray {
-        pos: apos,
-        dir: (inv_transform.transform(&tpos) - apos).normalized()
+        pos: inv_transform.transform(&ray.pos),
+        dir: inv_transform.transform_dir(&ray.dir),
+        t_min: ray.t_min,
+        t_max: ray.t_max
     }
 }
 
+/// Surface geometry at a point, in object space.
+struct LocalSurface {
+    /// The point moved onto the surface, with a bound on its error.
+    p: Vec3f,
+    p_error: Vec3f,
+    normal: Vec3f,
+    /// Interpolated from vertex normals where a mesh has them, otherwise
+    /// `normal`.
+    shading_normal: Vec3f,
+    uv: (float, float),
+    dpdu: Vec3f,
+    dpdv: Vec3f
+}
+
+/// Distance along `ray` to the triangle `a`, `b`, `c`, if it is hit within
+/// the ray's interval.
+pub fn intersect_triangle(a: &Vec3f, b: &Vec3f, c: &Vec3f, ray: &Ray) -> Option<float> {
+    let e1 = *b - *a;
+    let e2 = *c - *a;
+
+    let h = ray.dir.cross(&e2);
+    let aa = e1.dot(&h);
+
+    // exact, since object space rays are not unit length and small or
+    // scaled triangles have legitimately tiny determinants
+    if aa == 0.0 { return None }
+
+    let f = 1.0/aa;
+    let s = ray.pos - *a;
+    let u = f * s.dot(&h);
+
+    if u < 0.0 || u > 1.0 { return None }
+
+    let q = s.cross(&e1);
+    let v = f * ray.dir.dot(&q);
+
+    if v < 0.0 || u + v > 1.0 { return None }
+
+    let t = f * e2.dot(&q);
+
+    if ray.contains(t) { Some(t) } else { None }
+}
+
+/// Geometry at a point near the plane of a triangle, and the barycentric
+/// coordinates of the point.
+fn triangle_surface(p: &Vec3f, a: &Vec3f, b: &Vec3f, c: &Vec3f, uvs: &[(float, float), ..3])
+    -> (LocalSurface, (float, float, float))
+{
+    let (a, b, c) = (*a, *b, *c);
+    let (e1, e2, ep) = (b - a, c - a, *p - a);
+    let normal = e1.cross(&e2).normalized();
+    let (d11, d12, d22) = (e1.dot(&e1), e1.dot(&e2), e2.dot(&e2));
+    let (dp1, dp2) = (ep.dot(&e1), ep.dot(&e2));
+    let denom = d11 * d22 - d12 * d12;
+    let (b1, b2) = if denom == 0.0 { (0.0, 0.0) }
+                   else { ((d22 * dp1 - d12 * dp2) / denom,
+                           (d11 * dp2 - d12 * dp1) / denom) };
+    let b0 = 1.0 - b1 - b2;
+    let ((u0, v0), (u1, v1), (u2, v2)) = (uvs[0], uvs[1], uvs[2]);
+
+    let (du1, dv1, du2, dv2) = (u1 - u0, v1 - v0, u2 - u0, v2 - v0);
+    let det = du1 * dv2 - dv1 * du2;
+    let (dpdu, dpdv) = if det.abs() < 1e-12 {
+        random::coordinate_system(&normal)
+    } else {
+        ((e1 * dv2 - e2 * dv1) * (1.0 / det), (e2 * du1 - e1 * du2) * (1.0 / det))
+    };
+    let (pa, pb, pc) = (a * b0, b * b1, c * b2);
+    (LocalSurface {
+        p: pa + pb + pc,
+        p_error: (fperror::abs_vec(&pa) + fperror::abs_vec(&pb) + fperror::abs_vec(&pc))
+                 * fperror::gamma(7),
+        normal: normal,
+        shading_normal: normal,
+        uv: (b0 * u0 + b1 * u1 + b2 * u2, b0 * v0 + b1 * v1 + b2 * v2),
+        dpdu: dpdu,
+        dpdv: dpdv
+    }, (b0, b1, b2))
+}
+
 impl Object {
-    pub fn intersect<'a>(&'a self, ray: &Ray) -> Option<Intersection<'a>> {
-        match self.shape {
+    /// Where `ray` meets the object within the ray's interval, if it does.
+    pub fn hit(&self, ray: &Ray) -> Option<Hit> {
+        let ray = transform_ray(ray, &self.inv_transform);
+        let distance = match self.shape {
             Sphere { radius } => {
-                let ray = transform_ray(ray, &self.inv_transform);
                 let a = ray.dir.dot(&ray.dir);
                 let b = ray.dir.dot(&ray.pos) * 2.0;
                 let c = ray.pos.dot(&ray.pos) - radius * radius;
@@ -129,19 +377,22 @@ impl Object {
                     return None;
                 }
 
-                let a2inv = 1.0 / (2.0 * a);
-                let ts = [(-b + d.sqrt()) * a2inv,
-                          (-b - d.sqrt()) * a2inv];
-
-                Some(
-                    Intersection {
-                        object: self,
-                        distance: *ts.iter().min().unwrap()
-                    }
-                )
+                // avoids the cancellation in -b + sqrt(d) when b > 0
+                let q = if b < 0.0 { -0.5 * (b - d.sqrt()) } else { -0.5 * (b + d.sqrt()) };
+                if q == 0.0 { return None }
+                let (t0, t1) = (q / a, c / q);
+                let (t0, t1) = if t0 < t1 { (t0, t1) } else { (t1, t0) };
+
+                // the far root is the exit when the ray starts inside
+                if ray.contains(t0) {
+                    Some(t0)
+                } else if ray.contains(t1) {
+                    Some(t1)
+                } else {
+                    None
+                }
             },
             Box { aabb: aabb::AABB { min, max } } => {
-                let ray = transform_ray(ray, &self.inv_transform);
                 let ray_dir_inv = Vec3::new(1.0 / ray.dir.x, 1.0 / ray.dir.y, 1.0 / ray.dir.z);
                 let t1 = Vec3::new((min.x - ray.pos.x) * ray_dir_inv.x,
                                    (min.y - ray.pos.y) * ray_dir_inv.y,
@@ -153,60 +404,143 @@ impl Object {
                 let tmin = maxf(minf(t1.z, t2.z), maxf(minf(t1.y, t2.y), minf(t1.x, t2.x)));
                 let tmax = minf(maxf(t1.z, t2.z), minf(maxf(t1.y, t2.y), maxf(t1.x, t2.x)));
 
-                if tmax >= maxf(0.0, tmin) {
-                    Some(Intersection { object: self, distance: tmin })
+                if tmax < tmin {
+                    None
+                } else if ray.contains(tmin) {
+                    Some(tmin)
+                } else if ray.contains(tmax) {
+                    Some(tmax)
                 } else {
                     None
                 }
             },
-            Triangle { a, b, c } => {
-                let ray = transform_ray(ray, &self.inv_transform);
-
-                let e1 = b - a;
-                let e2 = c - a;
-
-                let h = ray.dir.cross(&e2);
-                let aa = e1.dot(&h);
-
-                if aa.approx_eq(&0.0) { return None }
-
-                let f = 1.0/aa;
-                let s = ray.pos - a;
-                let u = f * s.dot(&h);
-
-                if u < 0.0 || u > 1.0 { return None }
+            Triangle { a, b, c, _ } => intersect_triangle(&a, &b, &c, &ray),
+            Mesh { mesh: ref mesh } => {
+                return mesh.get().intersect(&ray).map(|&(prim, t)| Hit { distance: t, prim: prim });
+            }
+        };
+        distance.map(|&t| Hit { distance: t, prim: 0 })
+    }
 
-                let q = s.cross(&e1);
-                let v = f * ray.dir.dot(&q);
+    /// Maps a direction or tangent from object space to world space.
+    pub fn to_world_dir(&self, dir: &Vec3f) -> Vec3f {
+        self.transform.transform_dir(dir)
+    }
 
-                if v < 0.0 || u + v > 1.0 { return None }
+    /// Maps a normal from object space to a unit world space normal, using
+    /// the inverse transpose so that it stays perpendicular to the surface.
+    pub fn to_world_normal(&self, normal: &Vec3f) -> Vec3f {
+        self.inv_transform.transform_transposed(normal).normalized()
+    }
 
-                let t = f * e2.dot(&q);
+    /// Ratio of world to object space surface area around a point with
+    /// world space unit normal `normal`.
+    pub fn area_scale(&self, normal: &Vec3f) -> float {
+        self.transform.determinant().abs() / self.transform.transform_transposed(normal).norm()
+    }
 
-                Some(Intersection { object: self, distance: t })
-            }
+    /// The full intersection record for a hit found by `hit`.
+    pub fn intersection<'a>(&'a self, ray: &Ray, hit: &Hit) -> Intersection<'a> {
+        let approx = ray.pos + ray.dir * hit.distance;
+        let local = self.local_surface(&self.inv_transform.transform(&approx), hit.prim);
+        let (position, p_error) = self.transform.transform_with_error(&local.p, &local.p_error);
+        let normal = self.to_world_normal(&local.normal);
+        let shading = self.to_world_normal(&local.shading_normal);
+        Intersection {
+            distance: hit.distance,
+            object: self,
+            position: position,
+            p_error: p_error,
+            geometric_normal: normal,
+            shading_normal: if shading.dot(&normal) < 0.0 { -shading } else { shading },
+            uv: local.uv,
+            dpdu: self.to_world_dir(&local.dpdu),
+            dpdv: self.to_world_dir(&local.dpdv)
         }
     }
 
-    pub fn normal_at(&self, surface_pt: Vec3f) -> Vec3f {
+    /// Texture lookup point at a world-space point on the surface. `prim`
+    /// is the triangle the point lies on for meshes.
+    pub fn tex_point(&self, surface_pt: Vec3f, prim: uint) -> texture::TexPoint {
+        let p = self.inv_transform.transform(&surface_pt);
+        texture::TexPoint { p: p, uv: self.local_surface(&p, prim).uv }
+    }
+
+    /// Geometry at an object-space point on or near the surface. Spheres
+    /// use latitude and longitude around their local y axis for texture
+    /// coordinates, boxes map each face to the unit square and triangles
+    /// interpolate their vertex coordinates. The point is first moved onto
+    /// the surface, which bounds its error much more tightly than following
+    /// the ray would.
+    fn local_surface(&self, p: &Vec3f, prim: uint) -> LocalSurface {
         match self.shape {
-            Sphere { _ } => {
-                self.inv_transform.transform(&surface_pt).normalized()
+            Sphere { radius } => {
+                let p = *p * (radius / p.norm());
+                let two_pi = 2.0 * float::consts::pi;
+                let phi = p.z.atan2(&p.x);
+                let phi = if phi < 0.0 { phi + two_pi } else { phi };
+                let cos_theta = maxf(-1.0, minf(1.0, p.y / radius));
+                let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+                let (cos_phi, sin_phi) = (phi.cos(), phi.sin());
+                // v = 1 - theta / pi, so dtheta/dv = -pi
+                let dpdtheta = Vec3::new(radius * cos_theta * cos_phi, -radius * sin_theta,
+                                         radius * cos_theta * sin_phi);
+                LocalSurface {
+                    p: p,
+                    p_error: fperror::abs_vec(&p) * fperror::gamma(5),
+                    normal: p.normalized(),
+                    shading_normal: p.normalized(),
+                    uv: (phi / two_pi, 1.0 - cos_theta.acos() / float::consts::pi),
+                    dpdu: Vec3::new(-p.z * two_pi, 0.0, p.x * two_pi),
+                    dpdv: dpdtheta * -float::consts::pi
+                }
             },
             Box { aabb: aabb::AABB { min, max } } => {
-                let c1 = self.inv_transform.transform(&surface_pt) - min;
-                let c2 = self.inv_transform.transform(&surface_pt) - max;
-
-                     if c1.x.approx_eq(&0.0) { return Vec3::new(-1.0, 0.0, 0.0) }
-                else if c1.y.approx_eq(&0.0) { return Vec3::new( 0.0,-1.0, 0.0) }
-                else if c1.z.approx_eq(&0.0) { return Vec3::new( 0.0, 0.0,-1.0) }
-                else if c2.x.approx_eq(&0.0) { return Vec3::new( 1.0, 0.0, 0.0) }
-                else if c2.y.approx_eq(&0.0) { return Vec3::new( 0.0, 1.0, 0.0) }
-                else if c2.z.approx_eq(&0.0) { return Vec3::new( 0.0, 0.0, 1.0) }
-                else { fail!(~"impossible") }
+                let d = max - min;
+                // the face is the one the point lies closest to
+                let dists = [(p.x - min.x).abs(), (p.y - min.y).abs(), (p.z - min.z).abs(),
+                             (max.x - p.x).abs(), (max.y - p.y).abs(), (max.z - p.z).abs()];
+                let mut face = 0u;
+                for i in range(1u, 6) {
+                    if dists[i] < dists[face] { face = i }
+                }
+                let sign = if face < 3 { -1.0 } else { 1.0 };
+
+                // the coordinate across the face is then exact
+                let mut q = *p;
+                let mut p_error = fperror::abs_vec(p) * fperror::gamma(3);
+                match face {
+                    0 => { q.x = min.x; p_error.x = 0.0 },
+                    1 => { q.y = min.y; p_error.y = 0.0 },
+                    2 => { q.z = min.z; p_error.z = 0.0 },
+                    3 => { q.x = max.x; p_error.x = 0.0 },
+                    4 => { q.y = max.y; p_error.y = 0.0 },
+                    _ => { q.z = max.z; p_error.z = 0.0 }
+                }
+                let t = Vec3::new((q.x - min.x) / d.x, (q.y - min.y) / d.y, (q.z - min.z) / d.z);
+                let (normal, uv, dpdu, dpdv) = match face % 3 {
+                    0 => (Vec3::new(sign, 0.0, 0.0), (t.z, t.y),
+                          Vec3::new(0.0, 0.0, d.z), Vec3::new(0.0, d.y, 0.0)),
+                    1 => (Vec3::new(0.0, sign, 0.0), (t.x, t.z),
+                          Vec3::new(d.x, 0.0, 0.0), Vec3::new(0.0, 0.0, d.z)),
+                    _ => (Vec3::new(0.0, 0.0, sign), (t.x, t.y),
+                          Vec3::new(d.x, 0.0, 0.0), Vec3::new(0.0, d.y, 0.0))
+                };
+                LocalSurface { p: q, p_error: p_error, normal: normal, shading_normal: normal,
+                               uv: uv, dpdu: dpdu, dpdv: dpdv }
             },
-            Triangle { a, b, c } => {
-                (b - a).cross(&(c - a))
+            Triangle { a, b, c, uvs } => {
+                let (surface, _) = triangle_surface(p, &a, &b, &c, &uvs);
+                surface
+            },
+            Mesh { mesh: ref mesh } => {
+                let mesh = mesh.get();
+                let (a, b, c) = mesh.corners(prim);
+                let (surface, bary) = triangle_surface(p, &a, &b, &c, &mesh.corner_uvs(prim));
+                match mesh.interpolated_normal(prim, bary) {
+                    Some(n) => LocalSurface { shading_normal: n, ..surface },
+                    None => surface
+                }
             }
         }
     }
@@ -216,16 +550,23 @@ impl Object {
             Sphere { radius } => {
                 aabb::AABB::from_origin_extents(Vec3::new(0.0, 0.0, 0.0),
                                                 Vec3::new(radius, radius, radius))
-                           .transformed(&self.inv_transform.inv_transformation())
+                           .transformed(&self.transform)
             },
-            Box { aabb } => aabb.transformed(&self.inv_transform.inv_transformation()),
-            Triangle { a, b, c } => {
+            Box { aabb } => aabb.transformed(&self.transform),
+            Triangle { a, b, c, _ } => {
                 let xs = [a.x, b.x, c.x];
                 let ys = [a.y, b.y, c.y];
                 let zs = [a.z, b.z, c.z];
                 aabb::AABB::from_min_max(Vec3::new(*xs.iter().min().unwrap(), *ys.iter().min().unwrap(), *zs.iter().min().unwrap()),
                                          Vec3::new(*xs.iter().max().unwrap(), *ys.iter().max().unwrap(), *zs.iter().max().unwrap()))
-                           .transformed(&self.inv_transform.inv_transformation())
+                           .transformed(&self.transform)
+            },
+            Mesh { mesh: ref mesh } => {
+                let mut r = aabb::AABB::empty();
+                for p in mesh.get().positions.iter() {
+                    r.stretch_to_point(&self.transform.transform(p));
+                }
+                r
             }
         }
     }
@@ -234,5 +575,12 @@ impl Object {
 pub enum Shape {
     Sphere { radius: float },
     Box { aabb: aabb::AABB },
-    Triangle { a: Vec3f, b: Vec3f, c: Vec3f }
+    /// `uvs` are the texture coordinates at `a`, `b` and `c`.
+    Triangle { a: Vec3f, b: Vec3f, c: Vec3f, uvs: [(float, float), ..3] },
+    /// Triangles with shared vertex buffers, which several objects may
+    /// reference.
+    Mesh { mesh: Arc<mesh::TriangleMesh> }
 }
+
+/// Texture coordinates of triangles without any of their own.
+pub static DEFAULT_TRIANGLE_UVS: [(float, float), ..3] = [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)];
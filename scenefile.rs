@@ -0,0 +1,563 @@
+use std::{io, path};
+use extra::json;
+use extra::treemap::TreeMap;
+use nalgebra::vec::*;
+use nalgebra::mat::*;
+use transform::Affine;
+use image::RGB;
+use main::RenderOptions;
+use scene;
+use camera;
+use aabb;
+use obj;
+use bsdf;
+use bsdf::BSDF;
+use microfacet;
+use principled;
+use texture;
+use extra::arc::Arc;
+
+type Vec3f = Vec3<float>;
+type Fields = TreeMap<~str, json::Json>;
+/// Images loaded so far, by path, so materials share their texels.
+type TextureCache = TreeMap<~str, Arc<texture::ImageTexture>>;
+
+pub struct SceneDescription {
+    scene: scene::LinearScene,
+    camera: camera::Camera,
+    options: RenderOptions
+}
+
+pub fn load(path: &path::Path) -> Result<SceneDescription, ~str> {
+    match io::read_whole_file_str(path) {
+        Ok(src) => match from_str(src) {
+            Ok(desc) => Ok(desc),
+            Err(e) => Err(fmt!("%s: %s", path.to_str(), e))
+        },
+        Err(e) => Err(fmt!("%s: %s", path.to_str(), e))
+    }
+}
+
+pub fn from_str(src: &str) -> Result<SceneDescription, ~str> {
+    let root = match json::from_str(src) {
+        Ok(j) => j,
+        Err(e) => return Err(fmt!("%u:%u: %s", e.line, e.col, *e.msg))
+    };
+    let root = try!(as_object(&root, "scene"));
+
+    let options = match root.find(&~"options") {
+        Some(j) => try!(parse_options(j, "options")),
+        None => RenderOptions::new(240, 180)
+    };
+
+    let materials = match root.find(&~"materials") {
+        Some(j) => Some(try!(as_object(j, "materials"))),
+        None => None
+    };
+
+    let mut scene = scene::LinearScene { objs: ~[] };
+    let mut textures = TreeMap::new();
+    let objs = try!(as_list(try!(field(root, "scene", "objects")), "objects"));
+    for (i, o) in objs.iter().enumerate() {
+        try!(parse_object(o, fmt!("objects[%u]", i), materials, &mut textures, &mut scene));
+    }
+
+    let camera = try!(parse_camera(try!(field(root, "scene", "camera")), "camera", &options));
+
+    Ok(SceneDescription { scene: scene, camera: camera, options: options })
+}
+
+fn field<'a>(obj: &'a Fields, ctx: &str, name: &str) -> Result<&'a json::Json, ~str> {
+    match obj.find(&name.to_owned()) {
+        Some(j) => Ok(j),
+        None => Err(fmt!("%s: missing field '%s'", ctx, name))
+    }
+}
+
+fn as_object<'a>(j: &'a json::Json, ctx: &str) -> Result<&'a Fields, ~str> {
+    match *j {
+        json::Object(ref o) => Ok(&**o),
+        _ => Err(fmt!("%s: expected an object", ctx))
+    }
+}
+
+fn as_list<'a>(j: &'a json::Json, ctx: &str) -> Result<&'a [json::Json], ~str> {
+    match *j {
+        json::List(ref l) => Ok(l.as_slice()),
+        _ => Err(fmt!("%s: expected a list", ctx))
+    }
+}
+
+fn as_float(j: &json::Json, ctx: &str) -> Result<float, ~str> {
+    match *j {
+        json::Number(n) => Ok(n),
+        _ => Err(fmt!("%s: expected a number", ctx))
+    }
+}
+
+fn float_or(o: &Fields, ctx: &str, name: &str, default: float) -> Result<float, ~str> {
+    match o.find(&name.to_owned()) {
+        Some(j) => as_float(j, fmt!("%s.%s", ctx, name)),
+        None => Ok(default)
+    }
+}
+
+fn as_uint(j: &json::Json, ctx: &str) -> Result<uint, ~str> {
+    let n = try!(as_float(j, ctx));
+    if n < 1.0 || n != n.floor() {
+        return Err(fmt!("%s: expected a positive integer", ctx));
+    }
+    Ok(n as uint)
+}
+
+fn uint_or(o: &Fields, ctx: &str, name: &str, default: uint) -> Result<uint, ~str> {
+    match o.find(&name.to_owned()) {
+        Some(j) => as_uint(j, fmt!("%s.%s", ctx, name)),
+        None => Ok(default)
+    }
+}
+
+fn as_str<'a>(j: &'a json::Json, ctx: &str) -> Result<&'a str, ~str> {
+    match *j {
+        json::String(ref s) => Ok(s.as_slice()),
+        _ => Err(fmt!("%s: expected a string", ctx))
+    }
+}
+
+fn as_triple(j: &json::Json, ctx: &str) -> Result<(float, float, float), ~str> {
+    let l = try!(as_list(j, ctx));
+    if l.len() != 3 {
+        return Err(fmt!("%s: expected a list of three numbers", ctx));
+    }
+    Ok((try!(as_float(&l[0], fmt!("%s[0]", ctx))),
+        try!(as_float(&l[1], fmt!("%s[1]", ctx))),
+        try!(as_float(&l[2], fmt!("%s[2]", ctx)))))
+}
+
+fn as_pair(j: &json::Json, ctx: &str) -> Result<(float, float), ~str> {
+    let l = try!(as_list(j, ctx));
+    if l.len() != 2 {
+        return Err(fmt!("%s: expected a list of two numbers", ctx));
+    }
+    Ok((try!(as_float(&l[0], fmt!("%s[0]", ctx))),
+        try!(as_float(&l[1], fmt!("%s[1]", ctx)))))
+}
+
+fn as_vec(j: &json::Json, ctx: &str) -> Result<Vec3f, ~str> {
+    let (x, y, z) = try!(as_triple(j, ctx));
+    Ok(Vec3::new(x, y, z))
+}
+
+fn as_rgb(j: &json::Json, ctx: &str) -> Result<RGB, ~str> {
+    let (r, g, b) = try!(as_triple(j, ctx));
+    Ok(RGB { r: r, g: g, b: b })
+}
+
+fn parse_options(j: &json::Json, ctx: &str) -> Result<RenderOptions, ~str> {
+    let o = try!(as_object(j, ctx));
+    let width = match o.find(&~"width") {
+        Some(w) => try!(as_uint(w, fmt!("%s.width", ctx))),
+        None => 240
+    };
+    let height = match o.find(&~"height") {
+        Some(h) => try!(as_uint(h, fmt!("%s.height", ctx))),
+        None => 180
+    };
+    let mut opts = RenderOptions::new(width, height);
+    match o.find(&~"samples") {
+        Some(s) => opts.samples = Some(try!(as_uint(s, fmt!("%s.samples", ctx)))),
+        None => ()
+    }
+    Ok(opts)
+}
+
+fn parse_camera(j: &json::Json, ctx: &str, opts: &RenderOptions) -> Result<camera::Camera, ~str> {
+    let o = try!(as_object(j, ctx));
+    let position = try!(as_vec(try!(field(o, ctx, "position")), fmt!("%s.position", ctx)));
+    let lookat = try!(as_vec(try!(field(o, ctx, "lookat")), fmt!("%s.lookat", ctx)));
+    let fov = match o.find(&~"fov") {
+        Some(f) => try!(as_float(f, fmt!("%s.fov", ctx))),
+        None => 1.57
+    };
+    let aspect = match o.find(&~"aspect") {
+        Some(a) => try!(as_float(a, fmt!("%s.aspect", ctx))),
+        None => (opts.width as float) / (opts.height as float)
+    };
+    Ok(camera::Camera::new(position, lookat, fov, aspect))
+}
+
+static TRANSFORM_STEPS: &'static str = "'translate', 'rotate', 'scale' or 'matrix'";
+
+fn parse_transform(j: &json::Json, ctx: &str) -> Result<scene::Transform3d, ~str> {
+    let mut ts = Affine::identity();
+    for (i, step) in try!(as_list(j, ctx)).iter().enumerate() {
+        let sctx = fmt!("%s[%u]", ctx, i);
+        let s = try!(as_object(step, sctx));
+        if s.len() != 1 {
+            return Err(fmt!("%s: expected exactly one of %s", sctx, TRANSFORM_STEPS));
+        }
+        let (name, value) = s.iter().next().unwrap();
+        let vctx = fmt!("%s.%s", sctx, *name);
+        ts = match name.as_slice() {
+            "translate" => ts.translated(&try!(as_vec(value, vctx))),
+            "rotate" => ts.rotated(&try!(as_vec(value, vctx))),
+            "scale" => {
+                let s = match *value {
+                    json::Number(n) => Vec3::new(n, n, n),
+                    _ => try!(as_vec(value, vctx))
+                };
+                if s.x == 0.0 || s.y == 0.0 || s.z == 0.0 {
+                    return Err(fmt!("%s: scale factors must be non-zero", vctx));
+                }
+                ts.scaled(&s)
+            },
+            // three rows of a linear transformation, for shears
+            "matrix" => {
+                let rows = try!(as_list(value, vctx));
+                if rows.len() != 3 {
+                    return Err(fmt!("%s: expected three rows", vctx));
+                }
+                let m = Affine::from_rows(try!(as_vec(&rows[0], fmt!("%s[0]", vctx))),
+                                          try!(as_vec(&rows[1], fmt!("%s[1]", vctx))),
+                                          try!(as_vec(&rows[2], fmt!("%s[2]", vctx))));
+                if m.determinant() == 0.0 {
+                    return Err(fmt!("%s: matrix is singular", vctx));
+                }
+                ts.then(&m)
+            },
+            _ => return Err(fmt!("%s: expected exactly one of %s", sctx, TRANSFORM_STEPS))
+        };
+    }
+    Ok(ts)
+}
+
+fn parse_material(j: &json::Json, ctx: &str, materials: Option<&Fields>,
+                  textures: &mut TextureCache) -> Result<scene::Material, ~str>
+{
+    match *j {
+        json::String(ref name) => {
+            let found = match materials {
+                Some(ms) => ms.find(name),
+                None => None
+            };
+            return match found {
+                Some(m) => parse_material(m, fmt!("materials.%s", *name), None, textures),
+                None => Err(fmt!("%s: unknown material '%s'", ctx, *name))
+            };
+        }
+        _ => ()
+    }
+
+    let o = try!(as_object(j, ctx));
+    let color = match o.find(&~"color") {
+        Some(c) => try!(parse_texture(c, fmt!("%s.color", ctx), textures)),
+        None => texture::Constant(RGB::white())
+    };
+    let emission = match o.find(&~"emission") {
+        Some(e) => try!(parse_texture(e, fmt!("%s.emission", ctx), textures)),
+        None => texture::Constant(RGB::black())
+    };
+
+    let roughness = match o.find(&~"roughness") {
+        Some(&json::Number(r)) => Some(texture::Constant(RGB { r: r, g: r, b: r })),
+        Some(r) => Some(try!(parse_texture(r, fmt!("%s.roughness", ctx), textures))),
+        None => None
+    };
+
+    match o.find(&~"lobes") {
+        Some(l) => {
+            let lctx = fmt!("%s.lobes", ctx);
+            let mut lobes = ~[];
+            for (i, lobe) in try!(as_list(l, lctx)).iter().enumerate() {
+                lobes.push(try!(parse_lobe(lobe, fmt!("%s[%u]", lctx, i), roughness.is_some())));
+            }
+            if !(lobes.iter().fold(0.0, |acc, l| acc + l.weight) > 0.0) {
+                return Err(fmt!("%s: lobe weights must sum to more than zero", lctx));
+            }
+            let mut material = scene::Material::new(lobes, color, emission);
+            material.roughness = roughness;
+            return Ok(material);
+        }
+        None => ()
+    }
+
+    match o.find(&~"principled") {
+        Some(p) => {
+            let params = try!(parse_principled(p, fmt!("%s.principled", ctx)));
+            let mut material = scene::Material::principled(params, color, emission);
+            material.roughness = roughness;
+            return Ok(material);
+        }
+        None => ()
+    }
+
+    let specular = match o.find(&~"specular") {
+        Some(s) => try!(as_float(s, fmt!("%s.specular", ctx))),
+        None => 0.0
+    };
+    let refractive = match o.find(&~"refractive") {
+        Some(r) => try!(as_float(r, fmt!("%s.refractive", ctx))),
+        None => 0.0
+    };
+    let diffuse = match o.find(&~"diffuse") {
+        Some(d) => try!(as_float(d, fmt!("%s.diffuse", ctx))),
+        None => 1.0 - specular - refractive
+    };
+    if diffuse < 0.0 || specular < 0.0 || refractive < 0.0 ||
+       !(diffuse + specular + refractive).approx_eq(&1.0) {
+        return Err(fmt!("%s: diffuse, specular and refractive must be non-negative and sum to 1.0", ctx));
+    }
+    let ior = try!(float_or(o, ctx, "ior", 1.5));
+    if ior <= 0.0 {
+        return Err(fmt!("%s.ior: must be positive", ctx));
+    }
+
+    Ok(scene::Material::from_weights(diffuse, specular, refractive, ior, color, emission))
+}
+
+/// A colour is either an RGB triple or a texture object.
+fn parse_texture(j: &json::Json, ctx: &str, textures: &mut TextureCache)
+    -> Result<texture::Texture, ~str>
+{
+    match *j {
+        json::List(_) => return Ok(texture::Constant(try!(as_rgb(j, ctx)))),
+        _ => ()
+    }
+
+    let o = try!(as_object(j, ctx));
+    match try!(as_str(try!(field(o, ctx, "type")), fmt!("%s.type", ctx))) {
+        "image" => {
+            let file = try!(as_str(try!(field(o, ctx, "file")), fmt!("%s.file", ctx)));
+            let wrap = match o.find(&~"wrap") {
+                Some(w) => match texture::WrapMode::from_str(try!(as_str(w, fmt!("%s.wrap", ctx)))) {
+                    Some(w) => w,
+                    None => return Err(fmt!("%s.wrap: expected repeat, clamp or mirror", ctx))
+                },
+                None => texture::Repeat
+            };
+            let scale = match o.find(&~"scale") {
+                Some(&json::Number(n)) => RGB { r: n, g: n, b: n },
+                Some(s) => try!(as_rgb(s, fmt!("%s.scale", ctx))),
+                None => RGB::white()
+            };
+
+            let key = file.to_owned();
+            let image = match textures.find(&key) {
+                Some(image) => image.clone(),
+                None => match texture::ImageTexture::load(&path::Path(file)) {
+                    Ok(t) => Arc::new(t),
+                    Err(e) => return Err(fmt!("%s.file: %s", ctx, e))
+                }
+            };
+            textures.insert(key, image.clone());
+            Ok(texture::ImageMap { image: image, wrap: wrap, scale: scale })
+        },
+        "checker" => parse_procedural(o, ctx, texture::Checker),
+        "noise" => parse_procedural(o, ctx, texture::Noise),
+        "turbulence" => {
+            let octaves = try!(uint_or(o, ctx, "octaves", 6));
+            parse_procedural(o, ctx, texture::Turbulence { octaves: octaves })
+        },
+        "marble" => {
+            let octaves = try!(uint_or(o, ctx, "octaves", 6));
+            let distortion = try!(float_or(o, ctx, "distortion", 5.0));
+            parse_procedural(o, ctx, texture::Marble { octaves: octaves, distortion: distortion })
+        },
+        "wood" => {
+            let distortion = try!(float_or(o, ctx, "distortion", 0.5));
+            parse_procedural(o, ctx, texture::Wood { distortion: distortion })
+        },
+        "gradient" => {
+            let origin = match o.find(&~"origin") {
+                Some(v) => try!(as_vec(v, fmt!("%s.origin", ctx))),
+                None => Vec3::new(0.0, 0.0, 0.0)
+            };
+            let direction = match o.find(&~"direction") {
+                Some(v) => try!(as_vec(v, fmt!("%s.direction", ctx))),
+                None => Vec3::new(0.0, 1.0, 0.0)
+            };
+            parse_procedural(o, ctx, texture::Gradient { origin: origin, direction: direction })
+        },
+        other => Err(fmt!("%s.type: unknown texture type '%s'", ctx, other))
+    }
+}
+
+/// The fields shared by procedural textures: "space" ("uv" or "object"),
+/// "frequency" and the two "colors" blended between.
+fn parse_procedural(o: &Fields, ctx: &str, pattern: texture::Pattern)
+    -> Result<texture::Texture, ~str>
+{
+    let space = match o.find(&~"space") {
+        Some(s) => match try!(as_str(s, fmt!("%s.space", ctx))) {
+            "uv" => texture::UVSpace,
+            "object" => texture::ObjectSpace,
+            _ => return Err(fmt!("%s.space: expected 'uv' or 'object'", ctx))
+        },
+        None => texture::ObjectSpace
+    };
+    let frequency = try!(float_or(o, ctx, "frequency", 1.0));
+    let (low, high) = match o.find(&~"colors") {
+        Some(c) => {
+            let cctx = fmt!("%s.colors", ctx);
+            let l = try!(as_list(c, cctx));
+            if l.len() != 2 {
+                return Err(fmt!("%s: expected two colours", cctx));
+            }
+            (try!(as_rgb(&l[0], fmt!("%s[0]", cctx))), try!(as_rgb(&l[1], fmt!("%s[1]", cctx))))
+        },
+        None => (RGB::black(), RGB::white())
+    };
+    Ok(texture::Procedural { pattern: pattern, space: space, frequency: frequency,
+                             low: low, high: high })
+}
+
+fn parse_principled(j: &json::Json, ctx: &str) -> Result<principled::PrincipledParams, ~str> {
+    let o = try!(as_object(j, ctx));
+    let d = principled::PrincipledParams::default();
+    let unit = |name: &str, default: float| -> Result<float, ~str> {
+        let v = try!(float_or(o, ctx, name, default));
+        if v < 0.0 || v > 1.0 {
+            return Err(fmt!("%s.%s: must be between 0 and 1", ctx, name));
+        }
+        Ok(v)
+    };
+    let params = principled::PrincipledParams {
+        metallic: try!(unit("metallic", d.metallic)),
+        roughness: try!(unit("roughness", d.roughness)),
+        specular: try!(unit("specular", d.specular)),
+        specular_tint: try!(unit("specular_tint", d.specular_tint)),
+        sheen: try!(unit("sheen", d.sheen)),
+        sheen_tint: try!(unit("sheen_tint", d.sheen_tint)),
+        clearcoat: try!(unit("clearcoat", d.clearcoat)),
+        clearcoat_gloss: try!(unit("clearcoat_gloss", d.clearcoat_gloss)),
+        transmission: try!(unit("transmission", d.transmission)),
+        ior: try!(float_or(o, ctx, "ior", d.ior))
+    };
+    if params.ior <= 0.0 {
+        return Err(fmt!("%s.ior: must be positive", ctx));
+    }
+    Ok(params)
+}
+
+/// Smooth lobes are delta distributions everywhere on a surface, so they
+/// can't follow a roughness texture; `textured` rejects them.
+fn parse_lobe(j: &json::Json, ctx: &str, textured: bool) -> Result<scene::Lobe, ~str> {
+    let o = try!(as_object(j, ctx));
+    let weight = try!(float_or(o, ctx, "weight", 1.0));
+    if weight < 0.0 {
+        return Err(fmt!("%s.weight: must not be negative", ctx));
+    }
+
+    let bsdf = match try!(as_str(try!(field(o, ctx, "type")), fmt!("%s.type", ctx))) {
+        "lambertian" => ~bsdf::Lambertian as ~BSDF:Send+Freeze,
+        "mirror" => ~bsdf::SpecularReflection as ~BSDF:Send+Freeze,
+        "dielectric" => {
+            let ior = try!(float_or(o, ctx, "ior", 1.5));
+            if ior <= 0.0 {
+                return Err(fmt!("%s.ior: must be positive", ctx));
+            }
+            let roughness = try!(float_or(o, ctx, "roughness", 0.0));
+            if roughness < 0.0 || roughness > 1.0 {
+                return Err(fmt!("%s.roughness: must be between 0 and 1", ctx));
+            }
+            let distribution = microfacet::TrowbridgeReitz::from_roughness(roughness, roughness);
+            if textured && (roughness == 0.0 || distribution.effectively_smooth()) {
+                return Err(fmt!("%s.roughness: smooth lobes can't use a roughness texture", ctx));
+            }
+            if roughness > 0.0 {
+                ~bsdf::RoughDielectric {
+                    ior: ior,
+                    distribution: distribution
+                } as ~BSDF:Send+Freeze
+            } else {
+                ~bsdf::SpecularDielectric { ior: ior } as ~BSDF:Send+Freeze
+            }
+        },
+        "conductor" => {
+            let roughness = try!(float_or(o, ctx, "roughness", 0.2));
+            let roughness_v = try!(float_or(o, ctx, "roughness_v", roughness));
+            if roughness < 0.0 || roughness > 1.0 || roughness_v < 0.0 || roughness_v > 1.0 {
+                return Err(fmt!("%s: roughness must be between 0 and 1", ctx));
+            }
+            let fresnel = match (o.find(&~"f0"), o.find(&~"eta"), o.find(&~"k")) {
+                (Some(f0), None, None) => bsdf::SchlickF0(try!(as_rgb(f0, fmt!("%s.f0", ctx)))),
+                (None, Some(eta), Some(k)) =>
+                    bsdf::ComplexIOR(try!(as_rgb(eta, fmt!("%s.eta", ctx))),
+                                     try!(as_rgb(k, fmt!("%s.k", ctx)))),
+                (None, None, None) => bsdf::SchlickF0(RGB::white()),
+                _ => return Err(fmt!("%s: expected either 'f0' or both 'eta' and 'k'", ctx))
+            };
+            let distribution = microfacet::TrowbridgeReitz::from_roughness(roughness, roughness_v);
+            if textured && distribution.effectively_smooth() {
+                return Err(fmt!("%s.roughness: smooth lobes can't use a roughness texture", ctx));
+            }
+            ~bsdf::RoughConductor {
+                distribution: distribution,
+                fresnel: fresnel
+            } as ~BSDF:Send+Freeze
+        },
+        other => return Err(fmt!("%s.type: unknown lobe type '%s'", ctx, other))
+    };
+
+    Ok(scene::Lobe { weight: weight, bsdf: bsdf })
+}
+
+fn parse_object(j: &json::Json, ctx: &str, materials: Option<&Fields>,
+                textures: &mut TextureCache, scene: &mut scene::LinearScene) -> Result<(), ~str>
+{
+    let o = try!(as_object(j, ctx));
+    let transform = match o.find(&~"transform") {
+        Some(t) => try!(parse_transform(t, fmt!("%s.transform", ctx))),
+        None => Affine::identity()
+    };
+    let material = try!(parse_material(try!(field(o, ctx, "material")),
+                                       fmt!("%s.material", ctx), materials, textures));
+
+    let sctx = fmt!("%s.shape", ctx);
+    let s = try!(as_object(try!(field(o, ctx, "shape")), sctx));
+    let shape = match try!(as_str(try!(field(s, sctx, "type")), fmt!("%s.type", sctx))) {
+        "sphere" => {
+            let radius = try!(as_float(try!(field(s, sctx, "radius")), fmt!("%s.radius", sctx)));
+            if radius <= 0.0 {
+                return Err(fmt!("%s.radius: must be positive", sctx));
+            }
+            scene::Sphere { radius: radius }
+        },
+        "box" => {
+            let min = try!(as_vec(try!(field(s, sctx, "min")), fmt!("%s.min", sctx)));
+            let max = try!(as_vec(try!(field(s, sctx, "max")), fmt!("%s.max", sctx)));
+            if min.x > max.x || min.y > max.y || min.z > max.z {
+                return Err(fmt!("%s: min must not exceed max", sctx));
+            }
+            scene::Box { aabb: aabb::AABB::from_min_max(min, max) }
+        },
+        "triangle" => {
+            let uvs = match s.find(&~"uvs") {
+                Some(l) => {
+                    let uctx = fmt!("%s.uvs", sctx);
+                    let l = try!(as_list(l, uctx));
+                    if l.len() != 3 {
+                        return Err(fmt!("%s: expected three texture coordinates", uctx));
+                    }
+                    [try!(as_pair(&l[0], fmt!("%s[0]", uctx))),
+                     try!(as_pair(&l[1], fmt!("%s[1]", uctx))),
+                     try!(as_pair(&l[2], fmt!("%s[2]", uctx)))]
+                },
+                None => scene::DEFAULT_TRIANGLE_UVS
+            };
+            scene::Triangle { a: try!(as_vec(try!(field(s, sctx, "a")), fmt!("%s.a", sctx))),
+                              b: try!(as_vec(try!(field(s, sctx, "b")), fmt!("%s.b", sctx))),
+                              c: try!(as_vec(try!(field(s, sctx, "c")), fmt!("%s.c", sctx))),
+                              uvs: uvs }
+        },
+        "obj" => {
+            let file = try!(as_str(try!(field(s, sctx, "file")), fmt!("%s.file", sctx)));
+            try!(obj::load_obj(&path::Path(file), &transform, &material, scene));
+            return Ok(());
+        },
+        other => return Err(fmt!("%s.type: unknown shape type '%s'", sctx, other))
+    };
+
+    scene.objs.push(scene::Object::new(transform, shape, material));
+    Ok(())
+}
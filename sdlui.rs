@@ -2,6 +2,7 @@ use std::libc;
 use std::vec;
 use image::RGB;
 use main::RenderOptions;
+use tonemap::DisplayTransform;
 use std::unstable;
 
 struct SDL_Window;
@@ -35,10 +36,10 @@ pub struct UI {
     w: *SDL_Window
 }
 
+pub fn available() -> bool { true }
+
 fn f2i(x: float) -> u32 {
-    let mut x = x;
-    if x > 1.0 { x = 1.0 }
-    (x * 255.0) as u32
+    (x * 255.0 + 0.5) as u32
 }
 
 impl UI {
@@ -59,13 +60,14 @@ impl UI {
     }
 
     #[fixed_stack_segment]
-    pub fn paint(&mut self, data: &[RGB]) -> bool {
+    pub fn paint(&mut self, data: &[RGB], dt: &DisplayTransform) -> bool {
         unsafe {
             let surface = SDL_GetWindowSurface(self.w);
             SDL_LockSurface(surface);
             let pixels = (*surface).pixels as *mut u32;
             do vec::raw::mut_buf_as_slice(pixels, self.width*self.height) |pixs| {
-                for (i, &c) in data.iter().enumerate() {
+                for (i, c) in data.iter().enumerate() {
+                    let c = dt.apply(c);
                     pixs[i] = f2i(c.b) | (f2i(c.g) << 8) | (f2i(c.r) << 16);
                 }
             }
@@ -0,0 +1,272 @@
+use std::{io, path, uint, iterator};
+use std::ascii::StrAsciiExt;
+use extra::arc::Arc;
+use image::RGB;
+use tonemap;
+use tonemap::clamp01;
+use png;
+use noise;
+use nalgebra::vec::*;
+
+type Vec3f = Vec3<float>;
+
+/// What happens to texture coordinates outside [0, 1].
+#[deriving(Clone)]
+pub enum WrapMode {
+    Repeat,
+    Clamp,
+    Mirror
+}
+
+impl WrapMode {
+    pub fn from_str(s: &str) -> Option<WrapMode> {
+        match s {
+            "repeat" => Some(Repeat),
+            "clamp" => Some(Clamp),
+            "mirror" => Some(Mirror),
+            _ => None
+        }
+    }
+
+    fn apply(&self, i: int, n: int) -> int {
+        match *self {
+            Repeat => ((i % n) + n) % n,
+            Clamp => if i < 0 { 0 } else if i >= n { n - 1 } else { i },
+            Mirror => {
+                let m = ((i % (2 * n)) + 2 * n) % (2 * n);
+                if m >= n { 2 * n - 1 - m } else { m }
+            }
+        }
+    }
+}
+
+/// A bitmap of linear RGB texels, row-major from the top row.
+pub struct ImageTexture {
+    width: uint,
+    height: uint,
+    texels: ~[RGB]
+}
+
+impl ImageTexture {
+    /// Loads a .ppm or .png file. Texel values are assumed to be sRGB
+    /// encoded and are converted to linear.
+    pub fn load(path: &path::Path) -> Result<ImageTexture, ~str> {
+        let data = match io::read_whole_file(path) {
+            Ok(d) => d,
+            Err(e) => return Err(fmt!("%s: %s", path.to_str(), e))
+        };
+        let ext = path.filetype().map_default(~"", |e| e.to_ascii_lower());
+        let decoded = match ext.as_slice() {
+            ".png" => png::decode(data),
+            ".ppm" => decode_ppm(data),
+            _ => Err(~"expected a .png or .ppm file")
+        };
+        match decoded {
+            Ok((w, h, samples)) => Ok(ImageTexture::from_encoded(w, h, samples)),
+            Err(e) => Err(fmt!("%s: %s", path.to_str(), e))
+        }
+    }
+
+    /// `samples` holds three sRGB encoded values in [0, 1] per texel.
+    pub fn from_encoded(width: uint, height: uint, samples: &[float]) -> ImageTexture {
+        assert!(samples.len() == width * height * 3);
+        let mut texels = ~[];
+        for i in iterator::range(0, width * height) {
+            texels.push(RGB { r: tonemap::srgb_decode(samples[3 * i]),
+                              g: tonemap::srgb_decode(samples[3 * i + 1]),
+                              b: tonemap::srgb_decode(samples[3 * i + 2]) });
+        }
+        ImageTexture { width: width, height: height, texels: texels }
+    }
+
+    fn texel(&self, x: int, y: int, wrap: WrapMode) -> RGB {
+        let x = wrap.apply(x, self.width as int) as uint;
+        let y = wrap.apply(y, self.height as int) as uint;
+        self.texels[y * self.width + x]
+    }
+
+    /// Bilinearly filtered lookup. `v` points up, so (0, 0) is the bottom
+    /// left corner of the image as with OBJ texture coordinates.
+    pub fn bilinear(&self, u: float, v: float, wrap: WrapMode) -> RGB {
+        let x = u * (self.width as float) - 0.5;
+        let y = (1.0 - v) * (self.height as float) - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as int, y0 as int);
+
+        let top = self.texel(x0, y0, wrap).lerp(&self.texel(x0 + 1, y0, wrap), fx);
+        let bottom = self.texel(x0, y0 + 1, wrap).lerp(&self.texel(x0 + 1, y0 + 1, wrap), fx);
+        top.lerp(&bottom, fy)
+    }
+}
+
+/// Where a texture is looked up: the object-space position and the texture
+/// coordinates of a surface point.
+pub struct TexPoint {
+    p: Vec3f,
+    uv: (float, float)
+}
+
+/// The coordinates procedural patterns are evaluated in.
+#[deriving(Clone)]
+pub enum Space {
+    /// (u, v, 0)
+    UVSpace,
+    ObjectSpace
+}
+
+/// A procedural pattern, giving a blend factor in [0, 1] at a point.
+#[deriving(Clone)]
+pub enum Pattern {
+    /// Alternating unit cells.
+    Checker,
+    Noise,
+    Turbulence { octaves: uint },
+    /// Veins along x, perturbed by turbulence.
+    Marble { octaves: uint, distortion: float },
+    /// Rings around the y axis, perturbed by noise.
+    Wood { distortion: float },
+    /// Linear ramp from `origin` to `origin + direction`.
+    Gradient { origin: Vec3f, direction: Vec3f }
+}
+
+impl Pattern {
+    pub fn eval(&self, q: &Vec3f) -> float {
+        match *self {
+            Checker => {
+                let sum = (q.x.floor() + q.y.floor() + q.z.floor()) as int;
+                if sum & 1 == 0 { 0.0 } else { 1.0 }
+            },
+            Noise => clamp01(0.5 + 0.5 * noise::perlin(q)),
+            Turbulence { octaves } => clamp01(noise::turbulence(q, octaves)),
+            Marble { octaves, distortion } => {
+                0.5 + 0.5 * (q.x + distortion * noise::turbulence(q, octaves)).sin()
+            },
+            Wood { distortion } => {
+                let r = (q.x * q.x + q.z * q.z).sqrt() + distortion * noise::perlin(q);
+                r - r.floor()
+            },
+            Gradient { origin, direction } => {
+                let len2 = direction.dot(&direction);
+                if len2 == 0.0 { 0.0 } else { clamp01((*q - origin).dot(&direction) / len2) }
+            }
+        }
+    }
+}
+
+/// A colour that varies over a surface.
+#[deriving(Clone)]
+pub enum Texture {
+    Constant(RGB),
+    /// Image texels multiplied by `scale`.
+    ImageMap { image: Arc<ImageTexture>, wrap: WrapMode, scale: RGB },
+    /// Blends from `low` to `high` by `pattern`, evaluated at the lookup
+    /// coordinates multiplied by `frequency`.
+    Procedural { pattern: Pattern, space: Space, frequency: float, low: RGB, high: RGB }
+}
+
+impl Texture {
+    pub fn eval(&self, tp: &TexPoint) -> RGB {
+        match *self {
+            Constant(c) => c,
+            ImageMap { image: ref image, wrap, scale } => {
+                let (u, v) = tp.uv;
+                image.get().bilinear(u, v, wrap).mul_v(&scale)
+            },
+            Procedural { pattern: ref pattern, space, frequency, low, high } => {
+                let q = match space {
+                    UVSpace => {
+                        let (u, v) = tp.uv;
+                        Vec3::new(u, v, 0.0)
+                    },
+                    ObjectSpace => tp.p
+                };
+                low.lerp(&high, pattern.eval(&(q * frequency)))
+            }
+        }
+    }
+
+    /// A scalar such as roughness: the mean of the channels.
+    pub fn eval_float(&self, tp: &TexPoint) -> float {
+        let c = self.eval(tp);
+        (c.r + c.g + c.b) / 3.0
+    }
+
+    /// True if `eval` is black everywhere.
+    pub fn is_black(&self) -> bool {
+        match *self {
+            Constant(c) => c.is_black(),
+            ImageMap { scale, _ } => scale.is_black(),
+            Procedural { low, high, _ } => low.is_black() && high.is_black()
+        }
+    }
+}
+
+/// Splits the whitespace separated header fields of a PPM file, skipping
+/// comments. Returns the fields and the offset just past the single
+/// whitespace byte that ends the last one.
+fn ppm_header(data: &[u8], count: uint) -> Result<(~[~str], uint), ~str> {
+    let mut fields = ~[];
+    let mut pos = 0;
+    while fields.len() < count {
+        if pos >= data.len() { return Err(~"truncated PPM header") }
+        let c = data[pos] as char;
+        if c == '#' {
+            while pos < data.len() && data[pos] as char != '\n' { pos += 1 }
+        } else if c.is_whitespace() {
+            pos += 1;
+        } else {
+            let start = pos;
+            while pos < data.len() && !(data[pos] as char).is_whitespace() { pos += 1 }
+            fields.push(data.slice(start, pos).iter().map(|&b| b as char).collect());
+        }
+    }
+    Ok((fields, pos + 1))
+}
+
+/// Decodes a binary (P6) or ASCII (P3) PPM into the same form as
+/// `png::decode`.
+pub fn decode_ppm(data: &[u8]) -> Result<(uint, uint, ~[float]), ~str> {
+    let (header, body) = match ppm_header(data, 4) {
+        Ok(h) => h,
+        Err(e) => return Err(e)
+    };
+    let binary = match header[0].as_slice() {
+        "P6" => true,
+        "P3" => false,
+        _ => return Err(~"expected a P3 or P6 PPM file")
+    };
+    let (width, height, max) = match (uint::from_str(header[1]), uint::from_str(header[2]),
+                                      uint::from_str(header[3])) {
+        (Some(w), Some(h), Some(m)) if w > 0 && h > 0 && m > 0 && m < 65536 => (w, h, m),
+        _ => return Err(~"invalid PPM dimensions")
+    };
+    let count = width * height * 3;
+    let scale = 1.0 / (max as float);
+
+    let mut samples = ~[];
+    if binary {
+        let size = if max < 256 { 1 } else { 2 };
+        if body + count * size > data.len() { return Err(~"truncated PPM data") }
+        for i in iterator::range(0, count) {
+            let v = if size == 1 {
+                data[body + i] as uint
+            } else {
+                (data[body + 2 * i] as uint) << 8 | data[body + 2 * i + 1] as uint
+            };
+            samples.push((v as float) * scale);
+        }
+    } else {
+        let (values, _) = match ppm_header(data.slice(body - 1, data.len()), count) {
+            Ok(v) => v,
+            Err(_) => return Err(~"truncated PPM data")
+        };
+        for v in values.iter() {
+            match uint::from_str(*v) {
+                Some(n) => samples.push((n as float) * scale),
+                None => return Err(fmt!("invalid PPM sample '%s'", *v))
+            }
+        }
+    }
+    Ok((width, height, samples))
+}
@@ -0,0 +1,81 @@
+use image::RGB;
+
+pub enum Operator {
+    Clamp,
+    Reinhard,
+    Filmic,
+    Hable
+}
+
+/// Maps linear scene radiance to display values: scale by the exposure,
+/// compress with the tone mapping operator, then apply the sRGB transfer
+/// function. Results are in [0, 1].
+pub struct DisplayTransform {
+    exposure: float,
+    operator: Operator
+}
+
+pub fn clamp01(x: float) -> float {
+    if x > 1.0 { 1.0 }
+    else if x > 0.0 { x }
+    else { 0.0 }
+}
+
+/// The sRGB transfer function, mapping linear [0, 1] to encoded [0, 1].
+pub fn srgb_encode(x: float) -> float {
+    if x <= 0.0031308 { 12.92 * x }
+    else { 1.055 * x.pow(&(1.0 / 2.4)) - 0.055 }
+}
+
+/// Inverse of `srgb_encode`.
+pub fn srgb_decode(x: float) -> float {
+    if x <= 0.04045 { x / 12.92 }
+    else { ((x + 0.055) / 1.055).pow(&2.4) }
+}
+
+/// Narkowicz's fit of the ACES filmic reference rendering transform.
+fn aces(x: float) -> float {
+    (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)
+}
+
+/// Hable's Uncharted 2 curve, before normalisation to the white point.
+fn hable_partial(x: float) -> float {
+    let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+    ((x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f)) - e / f
+}
+
+static HABLE_WHITE: float = 11.2;
+
+impl Operator {
+    pub fn from_str(s: &str) -> Option<Operator> {
+        match s {
+            "clamp" => Some(Clamp),
+            "reinhard" => Some(Reinhard),
+            "filmic" | "aces" => Some(Filmic),
+            "hable" => Some(Hable),
+            _ => None
+        }
+    }
+
+    fn map(&self, x: float) -> float {
+        let x = if x > 0.0 { x } else { 0.0 };
+        match *self {
+            Clamp => x,
+            Reinhard => x / (1.0 + x),
+            Filmic => aces(x),
+            Hable => hable_partial(2.0 * x) / hable_partial(HABLE_WHITE)
+        }
+    }
+}
+
+impl DisplayTransform {
+    pub fn new() -> DisplayTransform {
+        DisplayTransform { exposure: 0.0, operator: Clamp }
+    }
+
+    pub fn apply(&self, c: &RGB) -> RGB {
+        let scale = (2.0f).pow(&self.exposure);
+        let f = |v: float| srgb_encode(clamp01(self.operator.map(v * scale)));
+        RGB { r: f(c.r), g: f(c.g), b: f(c.b) }
+    }
+}
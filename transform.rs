@@ -0,0 +1,247 @@
+use nalgebra::vec::*;
+use fperror;
+
+type Vec3f = Vec3<float>;
+
+/// An affine transformation `p -> M p + t`. The linear part may scale
+/// non-uniformly and shear, so directions and normals transform
+/// differently: see `transform_dir` and `transform_normal`.
+#[deriving(Clone)]
+pub struct Affine {
+    /// Rows of M.
+    rows: [Vec3f, ..3],
+    translation: Vec3f
+}
+
+impl Affine {
+    pub fn identity() -> Affine {
+        Affine::from_rows(Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, 0.0, 1.0))
+    }
+
+    /// A purely linear transformation with the given matrix rows.
+    pub fn from_rows(x: Vec3f, y: Vec3f, z: Vec3f) -> Affine {
+        Affine { rows: [x, y, z], translation: Vec3::new(0.0, 0.0, 0.0) }
+    }
+
+    /// Rotation by `|axis|` radians around `axis`.
+    pub fn rotation(axis: &Vec3f) -> Affine {
+        let angle = axis.norm();
+        if angle == 0.0 { return Affine::identity() }
+        let k = *axis * (1.0 / angle);
+        let (s, c) = (angle.sin(), angle.cos());
+        let d = 1.0 - c;
+        Affine::from_rows(Vec3::new(c + k.x * k.x * d, k.x * k.y * d - k.z * s, k.x * k.z * d + k.y * s),
+                          Vec3::new(k.y * k.x * d + k.z * s, c + k.y * k.y * d, k.y * k.z * d - k.x * s),
+                          Vec3::new(k.z * k.x * d - k.y * s, k.z * k.y * d + k.x * s, c + k.z * k.z * d))
+    }
+
+    pub fn scaling(s: &Vec3f) -> Affine {
+        Affine::from_rows(Vec3::new(s.x, 0.0, 0.0), Vec3::new(0.0, s.y, 0.0), Vec3::new(0.0, 0.0, s.z))
+    }
+
+    /// `other` applied after `self`.
+    pub fn then(&self, other: &Affine) -> Affine {
+        let cols = self.transposed_rows();
+        let row = |r: &Vec3f| Vec3::new(r.dot(&cols[0]), r.dot(&cols[1]), r.dot(&cols[2]));
+        Affine {
+            rows: [row(&other.rows[0]), row(&other.rows[1]), row(&other.rows[2])],
+            translation: other.transform(&self.translation)
+        }
+    }
+
+    /// Rows of the transpose of M, that is its columns.
+    fn transposed_rows(&self) -> [Vec3f, ..3] {
+        let (x, y, z) = (&self.rows[0], &self.rows[1], &self.rows[2]);
+        [Vec3::new(x.x, y.x, z.x), Vec3::new(x.y, y.y, z.y), Vec3::new(x.z, y.z, z.z)]
+    }
+
+    pub fn translated(&self, v: &Vec3f) -> Affine {
+        Affine { rows: self.rows, translation: self.translation + *v }
+    }
+
+    pub fn rotated(&self, axis: &Vec3f) -> Affine {
+        self.then(&Affine::rotation(axis))
+    }
+
+    pub fn scaled(&self, s: &Vec3f) -> Affine {
+        self.then(&Affine::scaling(s))
+    }
+
+    pub fn transform(&self, p: &Vec3f) -> Vec3f {
+        self.transform_dir(p) + self.translation
+    }
+
+    /// Transforms a point known to within `p_error` in each component,
+    /// returning it with a bound on the error of the result.
+    pub fn transform_with_error(&self, p: &Vec3f, p_error: &Vec3f) -> (Vec3f, Vec3f) {
+        let g = fperror::gamma(3);
+        let abs = Affine {
+            rows: [fperror::abs_vec(&self.rows[0]), fperror::abs_vec(&self.rows[1]),
+                   fperror::abs_vec(&self.rows[2])],
+            translation: fperror::abs_vec(&self.translation)
+        };
+        let rounding = abs.transform(&fperror::abs_vec(p)) * g;
+        let propagated = abs.transform_dir(p_error) * (1.0 + g);
+        (self.transform(p), rounding + propagated)
+    }
+
+    /// Applies only the linear part, as for directions and tangents.
+    pub fn transform_dir(&self, v: &Vec3f) -> Vec3f {
+        Vec3::new(self.rows[0].dot(v), self.rows[1].dot(v), self.rows[2].dot(v))
+    }
+
+    /// Multiplies by the transpose of the linear part. Normals transform by
+    /// the inverse transpose, so the normal transformation of an object is
+    /// this applied to its inverse.
+    pub fn transform_transposed(&self, v: &Vec3f) -> Vec3f {
+        self.rows[0] * v.x + self.rows[1] * v.y + self.rows[2] * v.z
+    }
+
+    pub fn determinant(&self) -> float {
+        self.rows[0].dot(&self.rows[1].cross(&self.rows[2]))
+    }
+
+    /// Fails if the linear part is singular.
+    pub fn inverse(&self) -> Affine {
+        let det = self.determinant();
+        if det == 0.0 { fail!(~"singular transformation") }
+        let (x, y, z) = (&self.rows[0], &self.rows[1], &self.rows[2]);
+        // the columns of the inverse are the cross products of the rows
+        let cofactors = Affine::from_rows(y.cross(z) * (1.0 / det), z.cross(x) * (1.0 / det),
+                                          x.cross(y) * (1.0 / det));
+        let rows = cofactors.transposed_rows();
+        let linear = Affine { rows: rows, translation: Vec3::new(0.0, 0.0, 0.0) };
+        Affine { rows: rows, translation: -linear.transform_dir(&self.translation) }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Affine;
+    use nalgebra::vec::*;
+    use scene;
+    use aabb::AABB;
+    use texture;
+    use image::RGB;
+
+    fn assert_close(a: &Vec3<float>, b: &Vec3<float>) {
+        let d = *a - *b;
+        assert!(d.x.abs() < 1e-9 && d.y.abs() < 1e-9 && d.z.abs() < 1e-9,
+                fmt!("%? != %?", *a, *b));
+    }
+
+    fn shear() -> Affine {
+        Affine::from_rows(Vec3::new(1.0, 0.6, 0.0), Vec3::new(0.0, 1.5, 0.0), Vec3::new(0.0, 0.0, 1.0))
+    }
+
+    fn object(transform: Affine, shape: scene::Shape) -> scene::Object {
+        let material = scene::Material::diffuse(texture::Constant(RGB::white()),
+                                                texture::Constant(RGB::black()));
+        scene::Object::new(transform, shape, material)
+    }
+
+    #[test]
+    fn inverse_round_trips() {
+        let p = Vec3::new(0.3, -1.2, 2.5);
+        let ts = [Affine::rotation(&Vec3::new(0.4, -0.2, 1.1)).translated(&Vec3::new(1.0, 2.0, 3.0)),
+                  Affine::scaling(&Vec3::new(2.0, 0.5, -3.0)).translated(&Vec3::new(-1.0, 0.0, 4.0)),
+                  shear().translated(&Vec3::new(0.5, -0.5, 0.0))];
+        for t in ts.iter() {
+            let inv = t.inverse();
+            assert_close(&inv.transform(&t.transform(&p)), &p);
+            assert_close(&t.transform(&inv.transform(&p)), &p);
+            assert_close(&t.then(&inv).transform(&p), &p);
+        }
+    }
+
+    #[test]
+    fn then_applies_other_last() {
+        let p = Vec3::new(1.0, 1.0, 1.0);
+        let scale = Affine::scaling(&Vec3::new(2.0, 3.0, 4.0));
+        let moved_then_scaled = Affine::identity().translated(&Vec3::new(1.0, 0.0, 0.0)).then(&scale);
+        assert_close(&moved_then_scaled.transform(&p), &Vec3::new(4.0, 3.0, 4.0));
+        let scaled_then_moved = scale.translated(&Vec3::new(1.0, 0.0, 0.0));
+        assert_close(&scaled_then_moved.transform(&p), &Vec3::new(3.0, 3.0, 4.0));
+    }
+
+    #[test]
+    fn normals_stay_perpendicular_to_sheared_tangents() {
+        let ts = shear();
+        let normal = Vec3::new(1.0, 1.0, 0.0);
+        let tangent = Vec3::new(1.0, -1.0, 0.0);
+        let world_normal = ts.inverse().transform_transposed(&normal);
+        let world_tangent = ts.transform_dir(&tangent);
+        assert!(world_normal.dot(&world_tangent).abs() < 1e-9);
+        // the sheared tangent alone would not be perpendicular to the untransformed normal
+        assert!(normal.dot(&world_tangent).abs() > 0.1);
+    }
+
+    #[test]
+    fn scaled_sphere_distances_are_in_world_space() {
+        let obj = object(Affine::scaling(&Vec3::new(2.0, 1.0, 1.0)), scene::Sphere { radius: 1.0 });
+        let along_x = scene::Ray::new(Vec3::new(-5.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        assert!((obj.hit(&along_x).unwrap().distance - 3.0).abs() < 1e-9);
+        let along_y = scene::Ray::new(Vec3::new(0.0, -5.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+        assert!((obj.hit(&along_y).unwrap().distance - 4.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn sheared_box_distances_are_in_world_space() {
+        let aabb = AABB::from_min_max(Vec3::new(-0.5, 0.0, -0.5), Vec3::new(0.5, 1.0, 0.5));
+        let obj = object(shear(), scene::Box { aabb: aabb });
+        // the top face is at world y = 1.5, over x in [-0.5, 0.5] + 0.6
+        let down = scene::Ray::new(Vec3::new(0.3, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        assert!((obj.hit(&down).unwrap().distance - 3.5).abs() < 1e-9);
+        // at world y = 0.75 the left face is at x = -0.5 + 0.3
+        let right = scene::Ray::new(Vec3::new(-5.0, 0.75, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        assert!((obj.hit(&right).unwrap().distance - 4.8).abs() < 1e-9);
+    }
+
+    #[test]
+    fn sheared_triangle_distances_are_in_world_space() {
+        let tri = scene::Triangle { a: Vec3::new(-1.0, 1.0, -1.0), b: Vec3::new(1.0, 1.0, -1.0),
+                                    c: Vec3::new(0.0, 1.0, 1.0), uvs: scene::DEFAULT_TRIANGLE_UVS };
+        let obj = object(shear(), tri);
+        // the triangle lies in world y = 1.5, shifted along x by 0.6
+        let down = scene::Ray::new(Vec3::new(0.6, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        assert!((obj.hit(&down).unwrap().distance - 3.5).abs() < 1e-9);
+        let past = scene::Ray::new(Vec3::new(-0.9, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        assert!(obj.hit(&past).is_none());
+    }
+
+    #[test]
+    fn world_normals_are_unit_and_perpendicular() {
+        let obj = object(shear().scaled(&Vec3::new(2.0, 0.5, 1.0)), scene::Sphere { radius: 1.0 });
+        let normal = Vec3::new(1.0, 1.0, 0.0).normalized();
+        let world_normal = obj.to_world_normal(&normal);
+        assert!((world_normal.norm() - 1.0).abs() < 1e-9);
+        for tangent in [Vec3::new(1.0, -1.0, 0.0), Vec3::new(0.0, 0.0, 1.0)].iter() {
+            assert!(world_normal.dot(&obj.to_world_dir(tangent)).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn area_scale_matches_transformed_triangle_area() {
+        let (a, b, c) = (Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.2), Vec3::new(0.3, 1.0, 0.0));
+        let ts = shear().scaled(&Vec3::new(2.0, 3.0, 0.5)).rotated(&Vec3::new(0.3, 0.2, 0.1));
+        let obj = object(ts.clone(), scene::Sphere { radius: 1.0 });
+        let object_area = 0.5 * (b - a).cross(&(c - a)).norm();
+        let (wa, wb, wc) = (ts.transform(&a), ts.transform(&b), ts.transform(&c));
+        let world_area = 0.5 * (wb - wa).cross(&(wc - wa)).norm();
+        let normal = obj.to_world_normal(&(b - a).cross(&(c - a)));
+        assert!((object_area * obj.area_scale(&normal) - world_area).abs() < 1e-9);
+
+        // a unit square in the xy plane scaled by 2 and 3
+        let scaled = object(Affine::scaling(&Vec3::new(2.0, 3.0, 4.0)), scene::Sphere { radius: 1.0 });
+        assert!((scaled.area_scale(&Vec3::new(0.0, 0.0, 1.0)) - 6.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn small_triangles_under_large_scales_are_hit() {
+        let tri = scene::Triangle { a: Vec3::new(0.0, 0.0, 0.0), b: Vec3::new(0.01, 0.0, 0.0),
+                                    c: Vec3::new(0.0, 0.01, 0.0), uvs: scene::DEFAULT_TRIANGLE_UVS };
+        let obj = object(Affine::scaling(&Vec3::new(1000.0, 1000.0, 1000.0)), tri);
+        let ray = scene::Ray::new(Vec3::new(2.0, 2.0, -5.0), Vec3::new(0.0, 0.0, 1.0));
+        assert!((obj.hit(&ray).unwrap().distance - 5.0).abs() < 1e-9);
+    }
+}